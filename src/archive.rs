@@ -0,0 +1,220 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::io::{Seek, Write};
+use std::path::Path;
+use flate2::Compression;
+use flate2::GzBuilder;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+use crate::segment::Segment;
+
+// ----------------------------------------------------------------------------
+// Writing segments into an archive, instead of a directory
+// ----------------------------------------------------------------------------
+//
+// Everything gets the same timestamp, and entries go in a fixed order, so
+// the same document always makes the same bytes.
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn from_path(path: &str) -> Option<ArchiveFormat> {
+        let path = path.to_lowercase();
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if path.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if path.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+// 1980-01-01, the earliest time a zip file can hold
+const MTIME: u64 = 315532800;
+
+const FILE_MODE: u32 = 0o644;
+const DIR_MODE: u32 = 0o755;
+
+// A relative path with only normal parts, or an error if it would land
+// outside the archive root. Same rules as writing to a directory.
+fn entry_path(file_name: &str) -> io::Result<String> {
+    let mut parts: Vec<&str> = vec![];
+    for part in file_name.split('/') {
+        match part {
+            "" | "." => {},
+            ".." => if parts.pop().is_none() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is outside the archive", file_name)));
+            },
+            part => parts.push(part),
+        }
+    }
+    match parts.is_empty() {
+        true => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} is not a file name", file_name))),
+        false => Ok(parts.join("/")),
+    }
+}
+
+// Where each file goes in the archive
+type Entry<'a> = (String, &'a Segment);
+
+// Every directory the files need, parents first, then the files themselves
+fn entries(segments: &[Segment]) -> io::Result<(Vec<String>, Vec<Entry<'_>>)> {
+    let mut dirs = BTreeSet::new();
+    let mut files = vec![];
+    for segment in segments {
+        let path = entry_path(&segment.file_name)?;
+        for (i, _) in path.match_indices('/') {
+            dirs.insert(path[..i + 1].to_string());
+        }
+        files.push((path, segment));
+    }
+    Ok((dirs.into_iter().collect(), files))
+}
+
+pub fn write_tar<W: Write>(out: W, segments: &[Segment]) -> io::Result<W> {
+    let (dirs, files) = entries(segments)?;
+    let mut builder = tar::Builder::new(out);
+    for dir in dirs {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(DIR_MODE);
+        header.set_mtime(MTIME);
+        header.set_size(0);
+        builder.append_data(&mut header, dir, io::empty())?;
+    }
+    for (path, segment) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(segment.mode.unwrap_or(FILE_MODE));
+        header.set_mtime(MTIME);
+        header.set_size(segment.contents.len() as u64);
+        builder.append_data(&mut header, path, segment.contents.as_bytes())?;
+    }
+    builder.into_inner()
+}
+
+pub fn write_tar_gz<W: Write>(out: W, segments: &[Segment]) -> io::Result<W> {
+    // No name or timestamp in the gzip header, so nothing varies
+    let gz = GzBuilder::new().mtime(0).write(out, Compression::default());
+    write_tar(gz, segments)?.finish()
+}
+
+pub fn write_zip<W: Write + Seek>(out: W, segments: &[Segment]) -> io::Result<W> {
+    let (dirs, files) = entries(segments)?;
+    let mtime = DateTime::from_date_and_time(1980, 1, 1, 0, 0, 0).map_err(io::Error::other)?;
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(mtime);
+
+    let mut zip = ZipWriter::new(out);
+    for dir in dirs {
+        zip.add_directory(dir, options.unix_permissions(DIR_MODE)).map_err(io::Error::other)?;
+    }
+    for (path, segment) in files {
+        zip.start_file(path, options.unix_permissions(segment.mode.unwrap_or(FILE_MODE)))
+            .map_err(io::Error::other)?;
+        zip.write_all(segment.contents.as_bytes())?;
+    }
+    zip.finish().map_err(io::Error::other)
+}
+
+// Write an archive file, in whichever format its name asks for
+pub fn write_archive<P: AsRef<Path>>(path: P, format: ArchiveFormat, segments: &[Segment]) -> io::Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut file = match format {
+        ArchiveFormat::Tar => write_tar(file, segments)?,
+        ArchiveFormat::TarGz => write_tar_gz(file, segments)?,
+        ArchiveFormat::Zip => write_zip(file, segments)?,
+    };
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn segments() -> Vec<Segment> {
+        vec![
+            Segment { file_name: "src/main.sh".into(), contents: "echo hi\n".into(), mode: Some(0o755), ..Default::default() },
+            Segment { file_name: "./README".into(), contents: "read me\n".into(), ..Default::default() },
+        ]
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(ArchiveFormat::from_path("code.tar.gz"), Some(ArchiveFormat::TarGz));
+        assert_eq!(ArchiveFormat::from_path("CODE.TGZ"), Some(ArchiveFormat::TarGz));
+        assert_eq!(ArchiveFormat::from_path("code.tar"), Some(ArchiveFormat::Tar));
+        assert_eq!(ArchiveFormat::from_path("code.zip"), Some(ArchiveFormat::Zip));
+        assert_eq!(ArchiveFormat::from_path("code.rar"), None);
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(entry_path("a/./b//c.txt").unwrap(), "a/b/c.txt");
+        assert_eq!(entry_path("/etc/passwd").unwrap(), "etc/passwd");
+        assert_eq!(entry_path("a/../b").unwrap(), "b");
+        assert!(entry_path("../escape").is_err());
+        assert!(entry_path("./").is_err());
+    }
+
+    #[test]
+    fn tar() {
+        let bytes = write_tar(vec![], &segments()).unwrap();
+        let mut archive = tar::Archive::new(&bytes[..]);
+        let mut seen = vec![];
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let header = entry.header();
+            let (path, mode, mtime) = (
+                entry.path().unwrap().to_string_lossy().into_owned(),
+                header.mode().unwrap(),
+                header.mtime().unwrap(),
+            );
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            seen.push((path, mode, mtime, contents));
+        }
+        assert_eq!(seen, vec![
+            ("src/".to_string(), 0o755, MTIME, "".to_string()),
+            ("src/main.sh".to_string(), 0o755, MTIME, "echo hi\n".to_string()),
+            ("README".to_string(), 0o644, MTIME, "read me\n".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn zip() {
+        let bytes = write_zip(Cursor::new(vec![]), &segments()).unwrap().into_inner();
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.file_names().count(), 3);
+
+        let mut file = archive.by_name("src/main.sh").unwrap();
+        assert_eq!(file.unix_mode().map(|m| m & 0o777), Some(0o755));
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "echo hi\n");
+    }
+
+    #[test]
+    fn reproducible() {
+        let gz = |s: &[Segment]| write_tar_gz(vec![], s).unwrap();
+        let zip = |s: &[Segment]| write_zip(Cursor::new(vec![]), s).unwrap().into_inner();
+        assert_eq!(gz(&segments()), gz(&segments()));
+        assert_eq!(zip(&segments()), zip(&segments()));
+    }
+
+    #[test]
+    fn escaping() {
+        let bad = [Segment { file_name: "../x".into(), ..Default::default() }];
+        assert!(write_tar(vec![], &bad).is_err());
+        assert!(write_zip(Cursor::new(vec![]), &bad).is_err());
+    }
+}
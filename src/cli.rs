@@ -0,0 +1,653 @@
+use tempdir::TempDir;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::error::Error;
+use std::process::ExitCode;
+use std::slice;
+use std::thread;
+use std::time::Duration;
+use clap::{Args, Parser, Subcommand};
+use glob::Pattern;
+
+use crate::add_languages;
+use crate::archive::{ArchiveFormat, write_archive};
+use crate::doctest::{Outcome, cases, run_cases};
+use crate::download;
+use crate::export::{Encoding, from_json, to_json};
+use crate::filetype::FileType;
+use crate::history::write_history;
+use crate::merge::{Conflict, merge};
+use crate::pack::pack;
+use crate::naming::check_template;
+use crate::plan::{Format, plan, table};
+use crate::project::{Loaded, Unpacked, blocks, exported, files, load, load_all, setup, sync_into, unpack, write_tree};
+use crate::segment::{Kind, Segment};
+use crate::watch::{Snapshot, documents, remove_stale, snapshot};
+use crate::steps::{StepError, exit_code, run_steps, shell};
+use crate::writer::{ExistingDir, prepare_dir, write_files};
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct CliArgs {
+    #[command(subcommand)]
+    action: Option<Action>,
+
+    #[command(flatten)]
+    unpack: UnpackArgs,
+}
+
+#[derive(Subcommand)]
+enum Action {
+    /// Run each document's `run` blocks, checking them against the `output`
+    /// blocks that follow them
+    Test(TestArgs),
+
+    /// Turn a directory back into Markdown, one fenced block per file
+    Pack(PackArgs),
+
+    /// Keep a directory in sync with a document as you edit it
+    Watch(WatchArgs),
+
+    /// Show the files a document would unpack to, without writing anything
+    Ls(LsArgs),
+
+    /// Print every block in the documents as JSON, for other tools
+    Export(ExportArgs),
+
+    /// Write the files from exported JSON into a directory
+    Import(ImportArgs),
+
+    /// Write a document's files into a .tar, .tar.gz or .zip archive
+    Archive(ArchiveArgs),
+
+    /// Make a git repository with a commit and a `step-N` tag for each
+    /// section of the document
+    Git(GitArgs),
+}
+
+#[derive(Args)]
+struct UnpackArgs {
+    /// Markdown files, URLs, directories of Markdown files, or globs, all
+    /// unpacked into the same tree in order. Settings like the command come
+    /// from the first document. A last argument that isn't any of those is
+    /// the command to run, like `glu doc.md 'make test'`.
+    #[arg(required = true, value_name = "MDFILES [COMMAND]")]
+    mdfiles: Vec<String>,
+
+    /// Command to run within the unpacked directory [default: the document's
+    /// `command`, or bash, or nothing when using --out]
+    #[arg(short, long)]
+    command: Option<String>,
+
+    /// Unpack into this directory and keep it, instead of a temp directory
+    #[arg(short, long)]
+    out: Option<String>,
+
+    /// What to do if the --out directory already has files in it
+    #[arg(long, value_enum, default_value_t, requires = "out")]
+    existing: ExistingDir,
+
+    /// What to do when several blocks write to the same file [default: the
+    /// document's `conflict`, or last-wins]. Blocks can override this with
+    /// `conflict=...` in their fence meta.
+    #[arg(long, value_enum)]
+    conflict: Option<Conflict>,
+
+    /// Run blocks marked `run` or `glu-exec` in their fence meta, in order,
+    /// after unpacking and before the command. Stops at the first failure.
+    #[arg(long)]
+    steps: bool,
+
+    /// When the command finishes, copy any edits to the unpacked files back
+    /// into the blocks they came from in the Markdown file
+    #[arg(long)]
+    sync_back: bool,
+
+    /// Only use the blocks up to the end of the first section whose heading
+    /// starts with this, so the files are as they should be at that point
+    #[arg(long, value_name = "HEADING", conflicts_with = "section")]
+    until: Option<String>,
+
+    /// Like --until, but by section number. 0 is everything before the first
+    /// section.
+    #[arg(long, value_name = "N")]
+    section: Option<usize>,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+// Which blocks to write, overriding the document's own `lang`, `only` and
+// `exclude` settings
+#[derive(Args)]
+struct FilterArgs {
+    /// Only write blocks in these languages, e.g. rust,bash
+    #[arg(long, value_delimiter = ',', value_parser = parse_lang)]
+    lang: Vec<String>,
+
+    /// Only write files whose path matches this glob. Can be repeated.
+    #[arg(long, value_name = "GLOB", value_parser = Pattern::new)]
+    only: Vec<Pattern>,
+
+    /// Never write files whose path matches this glob. Can be repeated.
+    #[arg(long, value_name = "GLOB", value_parser = Pattern::new)]
+    exclude: Vec<Pattern>,
+
+    /// Name blocks with no path from this template, using {index},
+    /// {heading}, {ext} and {lang} [default: {heading}-{index}.{ext}]
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_template)]
+    unnamed: Option<String>,
+
+    /// Leave out blocks with no path instead of naming them
+    #[arg(long)]
+    skip_unnamed: bool,
+}
+
+fn parse_lang(lang: &str) -> Result<String, String> {
+    FileType::known(lang).map(|_| lang.to_string())
+}
+
+fn parse_template(template: &str) -> Result<String, String> {
+    check_template(template).map(|_| template.to_string())
+}
+
+impl FilterArgs {
+    fn apply(&self, docs: &mut [Loaded]) {
+        for config in docs.iter_mut().map(|d| &mut d.doc.config) {
+            if !self.lang.is_empty() {
+                config.lang = self.lang.clone();
+            }
+            if !self.only.is_empty() {
+                config.only = self.only.clone();
+            }
+            if !self.exclude.is_empty() {
+                config.exclude = self.exclude.clone();
+            }
+            if self.unnamed.is_some() {
+                config.unnamed = self.unnamed.clone();
+            }
+            config.skip_unnamed |= self.skip_unnamed;
+        }
+    }
+}
+
+#[derive(Args)]
+struct TestArgs {
+    /// Markdown files, URLs, directories or globs to test
+    #[arg(required = true)]
+    mdfiles: Vec<String>,
+
+    /// What to do when several blocks write to the same file
+    #[arg(long, value_enum)]
+    conflict: Option<Conflict>,
+}
+
+#[derive(Args)]
+struct PackArgs {
+    /// Directory to pack
+    dir: String,
+
+    /// Write the Markdown here instead of to stdout
+    #[arg(short, long)]
+    out: Option<String>,
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    /// Markdown file to watch, or a directory of them
+    target: String,
+
+    /// Directory to keep up to date
+    #[arg(short, long)]
+    out: String,
+
+    /// What to do if the --out directory already has files in it at startup
+    #[arg(long, value_enum, default_value_t)]
+    existing: ExistingDir,
+
+    /// What to do when several blocks write to the same file
+    #[arg(long, value_enum)]
+    conflict: Option<Conflict>,
+
+    /// Run this after every rebuild, and wait for it. Without a value, runs
+    /// the document's configured `command`.
+    #[arg(short = 'x', long, num_args = 0..=1, default_missing_value = "")]
+    exec: Option<String>,
+
+    /// How often to check for changes, in milliseconds
+    #[arg(long, default_value_t = 500)]
+    interval: u64,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+#[derive(Args)]
+struct LsArgs {
+    /// Markdown files, URLs, directories or globs to look at
+    #[arg(required = true)]
+    mdfiles: Vec<String>,
+
+    /// How to print the list
+    #[arg(long, value_enum, default_value_t)]
+    format: Format,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// Markdown files, URLs, directories or globs to export
+    #[arg(required = true)]
+    mdfiles: Vec<String>,
+
+    /// A JSON array, or one JSON object per line
+    #[arg(long, value_enum, default_value_t)]
+    format: Encoding,
+
+    /// Write the JSON here instead of to stdout
+    #[arg(short, long)]
+    out: Option<String>,
+}
+
+#[derive(Args)]
+struct ImportArgs {
+    /// JSON or NDJSON from `glu export`, or - for stdin
+    input: String,
+
+    /// Directory to write the files into
+    #[arg(short, long)]
+    out: String,
+
+    /// What to do if the --out directory already has files in it
+    #[arg(long, value_enum, default_value_t)]
+    existing: ExistingDir,
+
+    /// What to do when several blocks write to the same file
+    #[arg(long, value_enum)]
+    conflict: Option<Conflict>,
+}
+
+#[derive(Args)]
+struct ArchiveArgs {
+    /// Markdown files, URLs, directories or globs to archive
+    #[arg(required = true)]
+    mdfiles: Vec<String>,
+
+    /// Archive to write. Its extension picks the format: .tar, .tar.gz, .tgz
+    /// or .zip
+    #[arg(short, long)]
+    out: String,
+
+    /// What to do when several blocks write to the same file
+    #[arg(long, value_enum)]
+    conflict: Option<Conflict>,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+#[derive(Args)]
+struct GitArgs {
+    /// Markdown file or URL
+    mdfile: String,
+
+    /// Directory for the new repository
+    #[arg(short, long)]
+    out: String,
+
+    /// What to do if the --out directory already has files in it
+    #[arg(long, value_enum, default_value_t)]
+    existing: ExistingDir,
+
+    /// What to do when several blocks write to the same file
+    #[arg(long, value_enum)]
+    conflict: Option<Conflict>,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+impl UnpackArgs {
+    // Exits however the command did
+    fn run(&self) -> Result<ExitCode, Box<dyn Error>> {
+        let (inputs, command) = self.split_command();
+        let mut docs = load_all(inputs)?;
+        self.filter.apply(&mut docs);
+        if self.sync_back && docs.iter().any(|d| download::is_url(&d.name)) {
+            return Err("--sync-back only works on local files".into());
+        }
+        if self.until.is_some() || self.section.is_some() {
+            if docs.len() > 1 {
+                return Err("--until and --section only work with a single document".into());
+            }
+            self.truncate(&mut docs[0])?;
+        }
+        let config = &docs[0].doc.config;
+        let steps: Vec<Segment> = match self.steps || config.steps {
+            true => docs.iter().flat_map(|d| d.doc.of_kind(Kind::Step)).collect(),
+            false => vec![],
+        };
+
+        let tmp: TempDir;
+        let root: &Path = match &self.out {
+            Some(out) => {
+                let inputs: Vec<&Path> = docs.iter().map(|d| Path::new(&d.name)).collect();
+                prepare_dir(out, self.existing, &inputs)?;
+                out.as_ref()
+            },
+            None => {
+                tmp = TempDir::new("glu")?;
+                tmp.path()
+            },
+        };
+        let unpacked = unpack(root, &docs, self.conflict)?;
+        print_reports(&unpacked);
+        run_steps(&steps, &unpacked.dir, &config.env, |line| eprintln!("{}", line))?;
+
+        // With --out, it's fine to just unpack and leave
+        let command = match (command, &config.command, &self.out) {
+            (Some(command), _, _) | (None, Some(command), _) => Some(command.as_str()),
+            (None, None, Some(_)) => None,
+            (None, None, None) => Some("bash"),
+        };
+        let code = match command {
+            Some(command) => run_command(command, &unpacked.dir, &config.env)?,
+            None => ExitCode::SUCCESS,
+        };
+
+        if self.sync_back {
+            for doc in &docs {
+                let synced = sync_into(doc, &unpacked)?;
+                for warning in &synced.warnings {
+                    eprintln!("warning: {}", warning);
+                }
+                if !synced.files.is_empty() {
+                    eprintln!("synced {} back into {}", synced.files.join(", "), doc.name);
+                }
+            }
+        }
+        Ok(code)
+    }
+
+    // `glu doc.md 'make test'` puts the command after the document, so a
+    // last argument that can't be a document is the command, unless -c
+    // already gave one
+    fn split_command(&self) -> (&[String], Option<&String>) {
+        if let ([inputs @ .., last], None) = (&self.mdfiles[..], &self.command) {
+            let document = download::is_url(last) || last.contains(['*', '?', '[']) || Path::new(last).exists();
+            if !inputs.is_empty() && !document {
+                return (inputs, Some(last));
+            }
+        }
+        (&self.mdfiles, self.command.as_ref())
+    }
+
+    // Stop the document where --until or --section says
+    fn truncate(&self, loaded: &mut Loaded) -> Result<(), Box<dyn Error>> {
+        let doc = &mut loaded.doc;
+        let number = match (&self.until, self.section) {
+            (Some(title), _) => match doc.find_section(title) {
+                Some(section) => section.number,
+                None => return Err(format!("{}: no section starting with {:?}", loaded.name, title).into()),
+            },
+            (None, Some(number)) if number > doc.sections.len() => {
+                return Err(format!("{}: there are only {} sections", loaded.name, doc.sections.len()).into());
+            },
+            (None, Some(number)) => number,
+            (None, None) => return Ok(()),
+        };
+        doc.truncate_after(number);
+        Ok(())
+    }
+}
+
+impl TestArgs {
+    // Fails unless every document passed
+    fn run(&self) -> Result<ExitCode, Box<dyn Error>> {
+        let mut all_passed = true;
+        for loaded in load_all(&self.mdfiles)? {
+            let tmp = TempDir::new("glu")?;
+            let docs = [loaded];
+            let unpacked = unpack(tmp.path(), &docs, self.conflict)?;
+            print_reports(&unpacked);
+
+            let doc = &docs[0].doc;
+            let (cases, warnings) = cases(&doc.segments);
+            for warning in &warnings {
+                eprintln!("warning: {}", warning);
+            }
+            let reports = run_cases(&cases, &unpacked.dir, &doc.config.env)?;
+            for report in &reports {
+                println!("{}", report);
+            }
+            let passed = reports.iter().filter(|r| r.outcome == Outcome::Pass).count();
+            let failed = reports.len() - passed;
+            println!("{}: {} passed, {} failed\n", docs[0].name, passed, failed);
+            all_passed &= failed == 0;
+        }
+        match all_passed {
+            true => Ok(ExitCode::SUCCESS),
+            false => Ok(ExitCode::FAILURE),
+        }
+    }
+}
+
+impl PackArgs {
+    fn run(&self) -> Result<ExitCode, Box<dyn Error>> {
+        let (md_text, warnings) = pack(&self.dir).map_err(|e| format!("{}: {}", self.dir, e))?;
+        for warning in &warnings {
+            eprintln!("warning: {}", warning);
+        }
+        match &self.out {
+            Some(out) => fs::write(out, md_text)?,
+            None => print!("{}", md_text),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+impl LsArgs {
+    fn run(&self) -> Result<ExitCode, Box<dyn Error>> {
+        let mut docs = load_all(&self.mdfiles)?;
+        self.filter.apply(&mut docs);
+        let entries = plan(&blocks(docs.iter().map(|d| &d.doc), None));
+        match self.format {
+            Format::Table => print!("{}", table(&entries)),
+            Format::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+impl ExportArgs {
+    fn run(&self) -> Result<ExitCode, Box<dyn Error>> {
+        let docs = load_all(&self.mdfiles)?;
+        let segments = exported(docs.iter().map(|d| &d.doc), None);
+        let json = to_json(&segments, self.format)?;
+        match &self.out {
+            Some(out) => fs::write(out, json)?,
+            None => print!("{}", json),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+impl ImportArgs {
+    fn run(&self) -> Result<ExitCode, Box<dyn Error>> {
+        let text = match self.input.as_str() {
+            "-" => io::read_to_string(io::stdin())?,
+            path => fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?,
+        };
+        let segments = from_json(&text).map_err(|e| format!("{}: {}", self.input, e))?;
+        let files = segments.into_iter().filter(|s| s.kind == Kind::File).collect();
+        let (files, reports) = merge(files, self.conflict.unwrap_or_default())?;
+        for report in &reports {
+            eprintln!("{}", report);
+        }
+        prepare_dir(&self.out, self.existing, &[Path::new(&self.input)])?;
+        write_files(&self.out, &files)?;
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+impl ArchiveArgs {
+    fn run(&self) -> Result<ExitCode, Box<dyn Error>> {
+        let format = ArchiveFormat::from_path(&self.out)
+            .ok_or_else(|| format!("{}: not a .tar, .tar.gz, .tgz or .zip file", self.out))?;
+        let mut docs = load_all(&self.mdfiles)?;
+        self.filter.apply(&mut docs);
+        let (mut files, reports) = files(docs.iter().map(|d| &d.doc), self.conflict)?;
+        for report in &reports {
+            eprintln!("{}", report);
+        }
+        if let Some(subdir) = &docs[0].doc.config.subdir {
+            for file in files.iter_mut() {
+                file.file_name = format!("{}/{}", subdir, file.file_name);
+            }
+        }
+        write_archive(&self.out, format, &files).map_err(|e| format!("{}: {}", self.out, e))?;
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+impl GitArgs {
+    fn run(&self) -> Result<ExitCode, Box<dyn Error>> {
+        let mut loaded = load(&self.mdfile)?;
+        self.filter.apply(slice::from_mut(&mut loaded));
+        let blocks = blocks([&loaded.doc], self.conflict);
+        prepare_dir(&self.out, self.existing, &[Path::new(&self.mdfile)])?;
+        let tags = write_history(Path::new(&self.out), &blocks, self.conflict.unwrap_or_default())?;
+        eprintln!("tagged {} in {}", tags.join(", "), self.out);
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+impl WatchArgs {
+    // Runs until interrupted
+    fn run(&self) -> Result<ExitCode, Box<dyn Error>> {
+        let target = Path::new(&self.target);
+        prepare_dir(&self.out, self.existing, &[target])?;
+        let mut last: Option<Snapshot> = None;
+        let mut written: Vec<PathBuf> = vec![];
+        let mut set_up: Option<String> = None;
+        loop {
+            let docs = documents(target).map_err(|e| format!("{}: {}", self.target, e))?;
+            let snap = snapshot(&docs);
+            if last.as_ref() != Some(&snap) {
+                last = Some(snap);
+                // A broken save shouldn't end the session, just wait for the next
+                match self.rebuild(&docs, &written, &mut set_up) {
+                    Ok(now) => written = now,
+                    Err(e) => eprintln!("glu: {}", e),
+                }
+            }
+            thread::sleep(Duration::from_millis(self.interval));
+        }
+    }
+
+    // Unpack every document again, and return what was written. The setup
+    // script only runs the first time, and again whenever it changes, since
+    // it's usually something slow like `npm install`. set_up is the script
+    // that last ran.
+    fn rebuild(&self, paths: &[PathBuf], before: &[PathBuf], set_up: &mut Option<String>) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let root = Path::new(&self.out);
+        let inputs: Vec<String> = paths.iter().map(|p| p.to_string_lossy().into_owned()).collect();
+        let mut docs = load_all(&inputs)?;
+        self.filter.apply(&mut docs);
+        let unpacked = write_tree(root, &docs, self.conflict)?;
+        print_reports(&unpacked);
+        let config = &docs[0].doc.config;
+        if config.setup != *set_up {
+            setup(config, &unpacked.dir)?;
+            *set_up = config.setup.clone();
+        }
+        for removed in remove_stale(root, before, &unpacked.written)? {
+            eprintln!("removed {}", removed.display());
+        }
+        eprintln!("unpacked {} files into {}", unpacked.written.len(), self.out);
+
+        if let Some(exec) = &self.exec {
+            let command = match exec.as_str() {
+                "" => config.command.as_deref(),
+                exec => Some(exec),
+            };
+            match command {
+                Some(command) => {
+                    let status = shell(command, &unpacked.dir, &config.env)?;
+                    eprintln!("{:?} exited with {}", command, exit_code(status));
+                },
+                None => eprintln!("warning: --exec given, but the document has no command"),
+            }
+        }
+        Ok(unpacked.written)
+    }
+}
+
+// How each file came together, for the user
+// Only files several blocks went into are worth mentioning
+fn print_reports(unpacked: &Unpacked) {
+    for report in unpacked.reports.iter().filter(|r| r.blocks > 1) {
+        eprintln!("{}", report);
+    }
+}
+
+fn run_command(command: &str, dir: &Path, env: &BTreeMap<String, String>) -> Result<ExitCode, Box<dyn Error>> {
+    let status = shell(command, dir, env).map_err(|e| format!("could not run {:?}: {}", command, e))?;
+    Ok(exit_code(status).into())
+}
+
+// ~/.config/glu/languages.toml, or wherever $GLU_LANGUAGES points
+fn user_languages_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("GLU_LANGUAGES") {
+        return Some(path.into());
+    }
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("glu").join("languages.toml"))
+}
+
+// Before anything looks a language up, --lang included
+fn load_user_languages() {
+    let Some(path) = user_languages_path() else {
+        return;
+    };
+    // Not having one is the usual case
+    let Ok(text) = fs::read_to_string(&path) else {
+        return;
+    };
+    if let Err(e) = add_languages(&text) {
+        eprintln!("warning: ignoring {}: {}", path.display(), e);
+    }
+}
+
+/// The glu command: parse the command line, run it, and exit how it says.
+pub fn main() -> ExitCode {
+    load_user_languages();
+    let cli = CliArgs::parse();
+    let result = match cli.action {
+        Some(Action::Test(args)) => args.run(),
+        Some(Action::Pack(args)) => args.run(),
+        Some(Action::Watch(args)) => args.run(),
+        Some(Action::Ls(args)) => args.run(),
+        Some(Action::Export(args)) => args.run(),
+        Some(Action::Import(args)) => args.run(),
+        Some(Action::Archive(args)) => args.run(),
+        Some(Action::Git(args)) => args.run(),
+        None => cli.unpack.run(),
+    };
+    // A failed step or setup script exits the way it did, like the command
+    result.unwrap_or_else(|err| {
+        eprintln!("glu: {}", err);
+        match err.downcast_ref::<StepError>() {
+            Some(failed) => exit_code(failed.status).into(),
+            None => ExitCode::FAILURE,
+        }
+    })
+}
@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use gray_matter::{Matter, Pod};
+use glob::Pattern;
+use gray_matter::engine::{TOML, YAML};
+use serde::{Deserialize, Deserializer};
+use crate::filetype::FileType;
+use crate::merge::Conflict;
+use crate::naming::{DEFAULT_TEMPLATE, check_template};
+use crate::segment::{Detection, Segment};
+
+// ----------------------------------------------------------------------------
+// Document-level configuration, from front matter
+// ----------------------------------------------------------------------------
+//
+// ---
+// title: Some article
+// glu:
+//   command: make test
+//   env:
+//     RUST_LOG: debug
+// ---
+//
+// Plenty of documents already have front matter for Jekyll, Hugo, Obsidian and
+// friends, so glu only looks under its own `glu` key and leaves the rest alone.
+// TOML front matter uses `+++` fences and a `[glu]` table.
+
+/// A document's settings, from the `glu` key of its front matter
+#[derive(PartialEq, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Run this instead of bash, unless the command line says otherwise
+    pub command: Option<String>,
+    /// Unpack into this subdirectory of the output directory
+    pub subdir: Option<String>,
+    /// Defaults for blocks of a given language, keyed by fence language
+    #[serde(deserialize_with = "deserialize_language_defaults")]
+    pub languages: BTreeMap<String, LanguageDefaults>,
+    /// File names that shouldn't be written at all
+    pub skip: Vec<String>,
+    /// Extra environment for the setup script and the command
+    pub env: BTreeMap<String, String>,
+    /// Shell script to run after unpacking, before the command
+    pub setup: Option<String>,
+    /// Document-wide policy for blocks that share a file
+    pub conflict: Option<Conflict>,
+    /// Run `run` blocks after unpacking, like --steps
+    pub steps: bool,
+    /// Only write files in these languages, if any are given
+    #[serde(deserialize_with = "deserialize_langs")]
+    pub lang: Vec<String>,
+    /// Only write files matching one of these globs, if any are given
+    #[serde(deserialize_with = "deserialize_globs")]
+    pub only: Vec<Pattern>,
+    /// Never write files matching these globs
+    #[serde(deserialize_with = "deserialize_globs")]
+    pub exclude: Vec<Pattern>,
+    /// How to name blocks with no path, instead of the default template
+    #[serde(deserialize_with = "deserialize_template")]
+    pub unnamed: Option<String>,
+    /// Leave blocks with no path out altogether
+    pub skip_unnamed: bool,
+}
+
+/// Settings for every block in one language, unless the block says otherwise
+#[derive(PartialEq, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LanguageDefaults {
+    /// Unix permissions for the files
+    #[serde(deserialize_with = "deserialize_mode")]
+    pub mode: Option<u32>,
+    /// How the blocks land on earlier blocks for the same file
+    pub conflict: Option<Conflict>,
+}
+
+// Modes are octal, but YAML reads `0755` as the integer 755. Either way, the
+// digits the author typed are what they meant.
+fn deserialize_mode<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw { Int(u64), Str(String) }
+
+    let digits = match Option::<Raw>::deserialize(d)? {
+        None => return Ok(None),
+        Some(Raw::Int(n)) => n.to_string(),
+        Some(Raw::Str(s)) => s,
+    };
+    u32::from_str_radix(digits.trim_start_matches("0o"), 8)
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom(format!("invalid file mode {:?}", digits)))
+}
+
+fn deserialize_language_defaults<'de, D>(d: D) -> Result<BTreeMap<String, LanguageDefaults>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let languages = BTreeMap::<String, LanguageDefaults>::deserialize(d)?;
+    for lang in languages.keys() {
+        FileType::known(lang).map_err(serde::de::Error::custom)?;
+    }
+    Ok(languages)
+}
+
+fn deserialize_globs<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Pattern>, D::Error> {
+    Vec::<String>::deserialize(d)?
+        .iter()
+        .map(|glob| Pattern::new(glob).map_err(|e| serde::de::Error::custom(format!("bad glob {:?}: {}", glob, e))))
+        .collect()
+}
+
+fn deserialize_langs<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    let langs = Vec::<String>::deserialize(d)?;
+    for lang in &langs {
+        FileType::known(lang).map_err(serde::de::Error::custom)?;
+    }
+    Ok(langs)
+}
+
+fn deserialize_template<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    let template = Option::<String>::deserialize(d)?;
+    if let Some(t) = &template {
+        check_template(t).map_err(serde::de::Error::custom)?;
+    }
+    Ok(template)
+}
+
+// Fence meta flags that keep a block out of the unpacked files
+const SKIP_FLAGS: [&str; 2] = ["skip", "glu:ignore"];
+
+/// Front matter that's there but doesn't make sense, and why
+#[derive(PartialEq, Debug)]
+pub struct ConfigError(pub String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bad glu front matter: {}", self.0)
+    }
+}
+
+impl Error for ConfigError {}
+
+fn front_matter(md_text: &str) -> Option<Pod> {
+    let first_line = md_text.lines().next().unwrap_or_default().trim_end();
+    match first_line {
+        "---" => Matter::<YAML>::new().parse(md_text).data,
+        "+++" => {
+            let mut matter = Matter::<TOML>::new();
+            matter.delimiter = "+++".into();
+            matter.parse(md_text).data
+        },
+        _ => None,
+    }
+}
+
+impl Config {
+    /// The settings in a document's front matter, or the defaults if it has
+    /// none (or only someone else's).
+    pub fn from_markdown(md_text: &str) -> Result<Config, ConfigError> {
+        let data = match front_matter(md_text) {
+            None => return Ok(Config::default()),
+            // gray_matter turns syntax errors into Null
+            Some(Pod::Null) => return Err(ConfigError("front matter could not be parsed".into())),
+            Some(data) => data,
+        };
+        let glu = match &data {
+            Pod::Hash(hash) => hash.get("glu"),
+            _ => None,
+        };
+        match glu {
+            None | Some(Pod::Null) => Ok(Config::default()),
+            Some(glu) => glu.deserialize().map_err(|e| ConfigError(e.to_string())),
+        }
+    }
+
+    // Whether a block makes it past the skip list, the language and path
+    // filters, and its own fence meta. Unnamed blocks can be left out too.
+    pub(crate) fn keeps(&self, segment: &Segment) -> bool {
+        let lang = |l: &String| String::from(FileType::from(l.as_str())) == segment.file_type;
+        if self.skip_unnamed && segment.detection == Detection::Fallback {
+            return false;
+        }
+        !self.skip.contains(&segment.file_name)
+            && !SKIP_FLAGS.iter().any(|f| segment.meta.has_flag(f))
+            && (self.lang.is_empty() || self.lang.iter().any(lang))
+            && (self.only.is_empty() || self.only.iter().any(|p| p.matches(&segment.file_name)))
+            && !self.exclude.iter().any(|p| p.matches(&segment.file_name))
+    }
+
+    // The template for naming this document's unnamed blocks
+    pub(crate) fn template(&self) -> &str {
+        self.unnamed.as_deref().unwrap_or(DEFAULT_TEMPLATE)
+    }
+
+    // Drop filtered files and fill in per-language defaults. Unnamed blocks
+    // should have their names by now, so filters can match them.
+    pub(crate) fn apply(&self, segments: Vec<Segment>) -> Vec<Segment> {
+        let languages: BTreeMap<String, &LanguageDefaults> = self.languages
+            .iter()
+            .map(|(lang, defaults)| (String::from(FileType::from(lang.as_str())), defaults))
+            .collect();
+
+        segments
+            .into_iter()
+            .filter(|s| self.keeps(s))
+            .map(|mut s| {
+                if let Some(defaults) = languages.get(&s.file_type) {
+                    s.mode = s.mode.or(defaults.mode);
+                    s.conflict = s.conflict.or(defaults.conflict);
+                }
+                s
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_front_matter() {
+        assert_eq!(Config::from_markdown(""), Ok(Config::default()));
+        assert_eq!(Config::from_markdown("# Hello\n\n---\n"), Ok(Config::default()));
+    }
+
+    #[test]
+    fn someone_elses_front_matter() {
+        let md = "---\ntitle: Hello\nlayout: post\n---\n# Hello\n";
+        assert_eq!(Config::from_markdown(md), Ok(Config::default()));
+    }
+
+    #[test]
+    fn yaml() {
+        let md = concat!(
+            "---\n",
+            "title: Hello\n",
+            "glu:\n",
+            "  command: make test\n",
+            "  subdir: project\n",
+            "  skip: [notes.txt]\n",
+            "  setup: npm install\n",
+            "  conflict: append\n",
+            "  steps: true\n",
+            "  lang: [rust]\n",
+            "  only: ['src/**']\n",
+            "  exclude: ['**/*.bak']\n",
+            "  unnamed: 'snippets/{index}.{ext}'\n",
+            "  skip-unnamed: true\n",
+            "  env:\n",
+            "    RUST_LOG: debug\n",
+            "  languages:\n",
+            "    bash:\n",
+            "      mode: 0755\n",
+            "    js:\n",
+            "      mode: '0600'\n",
+            "      conflict: error\n",
+            "---\n",
+            "# Hello\n",
+        );
+        let config = Config::from_markdown(md).unwrap();
+        assert_eq!(config.command, Some("make test".into()));
+        assert_eq!(config.subdir, Some("project".into()));
+        assert_eq!(config.skip, vec!["notes.txt".to_string()]);
+        assert_eq!(config.setup, Some("npm install".into()));
+        assert_eq!(config.conflict, Some(Conflict::Append));
+        assert!(config.steps);
+        assert_eq!(config.lang, vec!["rust".to_string()]);
+        assert_eq!(config.only, vec![Pattern::new("src/**").unwrap()]);
+        assert_eq!(config.exclude, vec![Pattern::new("**/*.bak").unwrap()]);
+        assert_eq!(config.unnamed, Some("snippets/{index}.{ext}".into()));
+        assert!(config.skip_unnamed);
+        assert_eq!(config.env.get("RUST_LOG"), Some(&"debug".to_string()));
+        assert_eq!(config.languages["bash"], LanguageDefaults { mode: Some(0o755), conflict: None });
+        assert_eq!(config.languages["js"], LanguageDefaults {
+            mode: Some(0o600),
+            conflict: Some(Conflict::Error),
+        });
+    }
+
+    #[test]
+    fn toml() {
+        let md = "+++\ntitle = \"Hello\"\n[glu]\ncommand = \"cargo test\"\n+++\n# Hello\n";
+        let config = Config::from_markdown(md).unwrap();
+        assert_eq!(config.command, Some("cargo test".into()));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Config::from_markdown("---\nglu: [unclosed\n---\n").is_err());
+        assert!(Config::from_markdown("---\nglu:\n  comand: typo\n---\n").is_err());
+        assert!(Config::from_markdown("---\nglu:\n  languages:\n    c:\n      mode: rwx\n---\n").is_err());
+        assert!(Config::from_markdown("---\nglu:\n  languages:\n    bsah:\n      mode: 0755\n---\n").is_err());
+        assert!(Config::from_markdown("---\nglu:\n  only: ['[']\n---\n").is_err());
+        assert!(Config::from_markdown("---\nglu:\n  unnamed: '{title}.txt'\n---\n").is_err());
+        assert!(Config::from_markdown("---\nglu:\n  lang: [rust, rsut]\n---\n").is_err());
+    }
+
+    #[test]
+    fn apply() {
+        let config = Config {
+            skip: vec!["notes.txt".into()],
+            languages: BTreeMap::from([
+                ("bash".to_string(), LanguageDefaults { mode: Some(0o700), conflict: None }),
+                ("py".to_string(), LanguageDefaults { mode: Some(0o700), conflict: Some(Conflict::Append) }),
+            ]),
+            ..Default::default()
+        };
+        let segments = config.apply(vec![
+            Segment { file_name: "notes.txt".into(), file_type: "unknown".into(), detection: Detection::Meta, ..Default::default() },
+            Segment { file_name: "run.sh".into(), file_type: "bash".into(), mode: Some(0o755), detection: Detection::Meta, ..Default::default() },
+            Segment { file_name: "main.py".into(), file_type: "python".into(), detection: Detection::Meta, ..Default::default() },
+        ]);
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].mode, Some(0o755));
+        assert_eq!(segments[1].mode, Some(0o700));
+        assert_eq!(segments[1].conflict, Some(Conflict::Append));
+    }
+
+    #[test]
+    fn filters() {
+        let seg = |name: &str, lang: &str, meta: &str| Segment {
+            file_name: name.into(),
+            file_type: lang.into(),
+            detection: Detection::Comment,
+            meta: meta.into(),
+            ..Default::default()
+        };
+        let names = |config: &Config| -> Vec<String> {
+            config.apply(vec![
+                seg("src/main.rs", "rust", ""),
+                seg("src/util.rs.bak", "rust", ""),
+                seg("run.sh", "bash", ""),
+                seg("scratch.rs", "rust", "skip"),
+                seg("notes.py", "python", "glu:ignore"),
+            ]).into_iter().map(|s| s.file_name).collect()
+        };
+
+        assert_eq!(names(&Config::default()), vec!["src/main.rs", "src/util.rs.bak", "run.sh"]);
+        assert_eq!(names(&Config { lang: vec!["rs".into(), "bash".into()], ..Default::default() }),
+            vec!["src/main.rs", "src/util.rs.bak", "run.sh"]);
+        assert_eq!(names(&Config { lang: vec!["bash".into()], ..Default::default() }), vec!["run.sh"]);
+        assert_eq!(names(&Config {
+            only: vec![Pattern::new("src/**").unwrap()],
+            exclude: vec![Pattern::new("*.bak").unwrap()],
+            ..Default::default()
+        }), vec!["src/main.rs"]);
+    }
+
+    #[test]
+    fn unnamed() {
+        let segments = || vec![
+            Segment { file_name: "main.rs".into(), file_type: "rust".into(), detection: Detection::Meta, ..Default::default() },
+            Segment { file_name: "snippet-02.rs".into(), file_type: "rust".into(), ..Default::default() },
+        ];
+        let names = |config: &Config| -> Vec<String> {
+            config.apply(segments()).into_iter().map(|s| s.file_name).collect()
+        };
+        assert_eq!(names(&Config::default()), vec!["main.rs", "snippet-02.rs"]);
+        assert_eq!(names(&Config { skip_unnamed: true, ..Default::default() }), vec!["main.rs"]);
+        assert_eq!(Config::default().template(), DEFAULT_TEMPLATE);
+        assert_eq!(Config { unnamed: Some("ex/{index}.{ext}".into()), ..Default::default() }.template(), "ex/{index}.{ext}");
+    }
+}
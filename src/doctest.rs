@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use crate::segment::{Kind, Segment};
+use crate::steps::check_step;
+
+// ----------------------------------------------------------------------------
+// Literate tests: run each `run` block, check the `output` block after it
+// ----------------------------------------------------------------------------
+
+#[derive(PartialEq, Debug)]
+pub struct Case<'a> {
+    pub command: &'a Segment,
+    pub expected: Option<&'a Segment>,
+}
+
+// Pair up each command with the expected output block right after it. An
+// expectation with no command before it is a mistake in the document, and
+// comes back as a warning rather than being silently dropped.
+pub fn cases(segments: &[Segment]) -> (Vec<Case<'_>>, Vec<String>) {
+    let mut cases: Vec<Case> = vec![];
+    let mut warnings: Vec<String> = vec![];
+    for segment in segments {
+        match segment.kind {
+            Kind::Step => cases.push(Case { command: segment, expected: None }),
+            Kind::Expect => match cases.last_mut() {
+                Some(case) if case.expected.is_none() => case.expected = Some(segment),
+                _ => warnings.push(format!("expected output with no command before it:\n{}", segment.contents)),
+            },
+            Kind::File => {},
+        }
+    }
+    (cases, warnings)
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Outcome {
+    Pass,
+    Failed(ExitStatus, String),
+    Mismatch(Vec<DiffLine>),
+}
+
+#[derive(PartialEq, Debug)]
+pub struct CaseReport {
+    pub summary: String,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for CaseReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.outcome {
+            Outcome::Pass => write!(f, "ok   {}", self.summary),
+            Outcome::Failed(status, output) => {
+                write!(f, "FAIL {} ({})\n{}", self.summary, status, output.trim_end())
+            },
+            Outcome::Mismatch(diff) => {
+                write!(f, "FAIL {} (output differs)", self.summary)?;
+                for line in diff {
+                    write!(f, "\n{}", line)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+// Run a command with stderr folded into stdout, so the two interleave the way
+// they would on a terminal.
+fn capture(script: &str, dir: &Path, env: &BTreeMap<String, String>) -> io::Result<(ExitStatus, String)> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("exec 2>&1\n{}", script))
+        .current_dir(dir)
+        .envs(env)
+        .output()?;
+    Ok((output.status, String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+// Trailing whitespace and blank lines aren't worth failing a test over
+fn normalize(text: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = text.lines().map(|l| l.trim_end()).collect();
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines
+}
+
+// Runs every case in order. A command that exits non-zero stops the rest,
+// since later steps usually build on it; mismatched output doesn't. Steps
+// that aren't shell stop everything before it starts.
+pub fn run_cases(cases: &[Case], dir: &Path, env: &BTreeMap<String, String>) -> io::Result<Vec<CaseReport>> {
+    for (i, case) in cases.iter().enumerate() {
+        check_step(case.command, i + 1).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    let mut reports = vec![];
+    for (i, case) in cases.iter().enumerate() {
+        let summary = format!("step {}: {}", i + 1, case.command.contents.lines().next().unwrap_or_default());
+        let (status, output) = capture(&case.command.contents, dir, env)?;
+        let outcome = if !status.success() {
+            Outcome::Failed(status, output)
+        } else {
+            match case.expected {
+                None => Outcome::Pass,
+                Some(expected) => {
+                    let (want, got) = (normalize(&expected.contents), normalize(&output));
+                    match want == got {
+                        true => Outcome::Pass,
+                        false => Outcome::Mismatch(diff(&want, &got)),
+                    }
+                },
+            }
+        };
+        let stop = matches!(outcome, Outcome::Failed(..));
+        reports.push(CaseReport { summary: summary, outcome: outcome });
+        if stop {
+            break;
+        }
+    }
+    Ok(reports)
+}
+
+// ----------------------------------------------------------------------------
+// Line diff
+// ----------------------------------------------------------------------------
+
+#[derive(PartialEq, Debug)]
+pub enum DiffLine {
+    Same(String),
+    Expected(String),
+    Actual(String),
+}
+
+impl fmt::Display for DiffLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiffLine::Same(l) => write!(f, "  {}", l),
+            DiffLine::Expected(l) => write!(f, "- {}", l),
+            DiffLine::Actual(l) => write!(f, "+ {}", l),
+        }
+    }
+}
+
+// Plain longest-common-subsequence diff. Expected output blocks are short, so
+// the quadratic table is fine.
+pub fn diff(want: &[&str], got: &[&str]) -> Vec<DiffLine> {
+    let (n, m) = (want.len(), got.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = match want[i] == got[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut out = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && want[i] == got[j] {
+            out.push(DiffLine::Same(want[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(DiffLine::Expected(want[i].to_string()));
+            i += 1;
+        } else {
+            out.push(DiffLine::Actual(got[j].to_string()));
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn seg(kind: Kind, contents: &str) -> Segment {
+        Segment {
+            kind: kind,
+            contents: contents.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cases() {
+        let segments = vec![
+            seg(Kind::File, "file\n"),
+            seg(Kind::Step, "one\n"),
+            seg(Kind::Expect, "1\n"),
+            seg(Kind::Step, "two\n"),
+            seg(Kind::Step, "three\n"),
+            seg(Kind::Expect, "3\n"),
+            seg(Kind::Expect, "orphan\n"),
+        ];
+        let (found, warnings) = cases(&segments);
+        assert_eq!(found, vec![
+            Case { command: &segments[1], expected: Some(&segments[2]) },
+            Case { command: &segments[3], expected: None },
+            Case { command: &segments[4], expected: Some(&segments[5]) },
+        ]);
+        assert_eq!(warnings, vec!["expected output with no command before it:\norphan\n"]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(""), Vec::<&str>::new());
+        assert_eq!(normalize("a  \nb\n\n\n"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff(&["a", "b", "c"], &["a", "x", "c", "d"]), vec![
+            DiffLine::Same("a".into()),
+            DiffLine::Expected("b".into()),
+            DiffLine::Actual("x".into()),
+            DiffLine::Same("c".into()),
+            DiffLine::Actual("d".into()),
+        ]);
+        assert_eq!(diff(&[], &[]), vec![]);
+    }
+
+    #[test]
+    fn test_run_cases() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let segments = vec![
+            seg(Kind::Step, "echo hello\necho oops >&2\n"),
+            seg(Kind::Expect, "hello\noops\n"),
+            seg(Kind::Step, "echo right\n"),
+            seg(Kind::Expect, "wrong\n"),
+            seg(Kind::Step, "exit 2\n"),
+            seg(Kind::Step, "echo unreachable\n"),
+        ];
+        let reports = run_cases(&cases(&segments).0, tmp.path(), &BTreeMap::new()).unwrap();
+
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].outcome, Outcome::Pass);
+        assert_eq!(reports[1].outcome, Outcome::Mismatch(vec![
+            DiffLine::Expected("wrong".into()),
+            DiffLine::Actual("right".into()),
+        ]));
+        assert!(matches!(reports[2].outcome, Outcome::Failed(..)));
+        assert_eq!(reports[1].to_string(), "FAIL step 2: echo right (output differs)\n- wrong\n+ right");
+    }
+
+    #[test]
+    fn test_indented_output() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let segments = crate::parse::parse("```bash run\nprintf '  a\\n  b\\n'\n```\n\n```output\n  a\n  b\n```\n");
+        let reports = run_cases(&cases(&segments).0, tmp.path(), &BTreeMap::new()).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].outcome, Outcome::Pass, "{}", reports[0]);
+    }
+}
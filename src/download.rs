@@ -2,13 +2,9 @@ use url::Url;
 use std::error::Error;
 use std::fs;
 
-fn is_url(path: &str) -> bool {
+pub fn is_url(path: &str) -> bool {
     match Url::parse(path) {
-        Ok(url) => match url.scheme() {
-            "http" => true,
-            "https" => true,
-            _ => false,
-        },
+        Ok(url) => matches!(url.scheme(), "http" | "https"),
         Err(_) => false,
     }
 }
@@ -35,14 +31,14 @@ mod test {
 
     #[test]
     fn test_is_url() {
-        assert_eq!(is_url(""), false);
-        assert_eq!(is_url("file.md"), false);
-        assert_eq!(is_url("/path/to/file.md"), false);
-        assert_eq!(is_url("../path/to/file.md"), false);
-
-        assert_eq!(is_url("http://foo/bar"), true);
-        assert_eq!(is_url("https://foo/bar"), true);
-        assert_eq!(is_url("ftp://foo/bar"), false);
+        assert!(!is_url(""));
+        assert!(!is_url("file.md"));
+        assert!(!is_url("/path/to/file.md"));
+        assert!(!is_url("../path/to/file.md"));
+
+        assert!(is_url("http://foo/bar"));
+        assert!(is_url("https://foo/bar"));
+        assert!(!is_url("ftp://foo/bar"));
     }
 
     #[test]
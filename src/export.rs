@@ -0,0 +1,88 @@
+use clap::ValueEnum;
+use crate::segment::Segment;
+
+// ----------------------------------------------------------------------------
+// Segments as JSON, for other tools to read and write
+// ----------------------------------------------------------------------------
+//
+// Each segment is an object with every field of `Segment`: kind, file_type,
+// file_name, detection, contents, conflict, mode, meta, source, section and
+// guess. Only file_name and contents are needed on the way back in, the rest
+// default. `project::exported` picks the segments, so a document's export
+// imports as the same files `glu` would unpack.
+
+#[derive(PartialEq, Debug, Copy, Clone, Default, ValueEnum)]
+pub enum Encoding {
+    /// One JSON array
+    #[default]
+    Json,
+    /// One JSON object per line
+    Ndjson,
+}
+
+pub fn to_json(segments: &[Segment], encoding: Encoding) -> serde_json::Result<String> {
+    match encoding {
+        Encoding::Json => Ok(serde_json::to_string_pretty(segments)? + "\n"),
+        Encoding::Ndjson => {
+            let mut out = String::new();
+            for segment in segments {
+                out += &serde_json::to_string(segment)?;
+                out += "\n";
+            }
+            Ok(out)
+        },
+    }
+}
+
+// Either encoding: an array, or one object per line
+pub fn from_json(text: &str) -> serde_json::Result<Vec<Segment>> {
+    if text.trim_start().starts_with('[') {
+        return serde_json::from_str(text);
+    }
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+    use crate::segment::Kind;
+
+    const DOC: &str = "```python title=a.py mode=0755\nprint(1)\n```\n\n```bash run\necho hi\n```\n";
+
+    #[test]
+    fn round_trip() {
+        let segments = parse(DOC);
+        for encoding in [Encoding::Json, Encoding::Ndjson] {
+            let json = to_json(&segments, encoding).unwrap();
+            assert_eq!(from_json(&json).unwrap(), segments, "{}", json);
+        }
+        assert_eq!(to_json(&segments, Encoding::Ndjson).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn fields() {
+        let json: serde_json::Value = serde_json::from_str(&to_json(&parse(DOC), Encoding::Json).unwrap()).unwrap();
+        assert_eq!(json[0]["file_name"], "a.py");
+        assert_eq!(json[0]["file_type"], "python");
+        assert_eq!(json[0]["detection"], "meta");
+        assert_eq!(json[0]["mode"], 0o755);
+        assert_eq!(json[0]["meta"]["attrs"]["title"], "a.py");
+        assert_eq!(json[0]["source"]["start_line"], 1);
+        assert_eq!(json[1]["kind"], "step");
+        assert_eq!(json[1]["meta"]["flags"][0], "run");
+    }
+
+    #[test]
+    fn minimal_input() {
+        let segments = from_json("{\"file_name\": \"x.txt\", \"contents\": \"x\\n\"}\n\n{\"file_name\": \"y.txt\"}\n").unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].kind, Kind::File);
+        assert_eq!(segments[0].contents, "x\n");
+        assert_eq!(segments[1].file_name, "y.txt");
+        assert!(from_json("{\"file_name\": 3}").is_err());
+    }
+}
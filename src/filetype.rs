@@ -1,97 +1,231 @@
+use std::fmt;
+use std::ptr;
+use std::sync::OnceLock;
 use regex::Regex;
-use strum_macros::EnumIter;
+use serde::Deserialize;
+
+// ----------------------------------------------------------------------------
+// Language registry
+// ----------------------------------------------------------------------------
+//
+// Languages are data, not code: src/languages.toml is compiled in, and teams
+// can add their own in the same format without forking glu. The glu command
+// reads those from ~/.config/glu/languages.toml, or wherever $GLU_LANGUAGES
+// points; library users hand them to `add_languages`. A language there with
+// the same name as a built-in one replaces it. Either way it happens once,
+// before the first lookup, since every FileType points into the registry.
+
+/// Everything glu knows about a language.
+#[derive(PartialEq, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Language {
+    /// What blocks of this language are called, e.g. in `glu ls`
+    pub name: String,
+    /// Other fence labels that mean this language
+    pub aliases: Vec<String>,
+    /// Extensions a path comment can name, the usual one first
+    pub extensions: Vec<String>,
+    /// Whole file names, for files with no extension like `Makefile`
+    pub filenames: Vec<String>,
+    /// What starts a comment that runs to the end of the line, like `//`
+    pub line_comment: Option<String>,
+    /// What opens and closes a comment, like `/*` and `*/`
+    pub block_comment: Option<(String, String)>,
+    /// Programs a shebang line might run it with
+    pub interpreters: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LanguageFile {
+    #[serde(default)]
+    language: Vec<Language>,
+}
+
+const BUILTIN: &str = include_str!("languages.toml");
+
+/// Languages from a TOML file in the same format as the built-in one.
+pub fn parse_languages(text: &str) -> Result<Vec<Language>, toml::de::Error> {
+    Ok(toml::from_str::<LanguageFile>(text)?.language)
+}
+
+// The user's languages come first, so their aliases and extensions win
+fn with_user_languages(mut builtin: Vec<Language>, user: Vec<Language>) -> Vec<Language> {
+    builtin.retain(|b| user.iter().all(|u| u.name != b.name));
+    user.into_iter().chain(builtin).collect()
+}
+
+fn builtin_languages() -> Vec<Language> {
+    parse_languages(BUILTIN).expect("built-in languages.toml is broken")
+}
+
+static LANGUAGES: OnceLock<Vec<Language>> = OnceLock::new();
+
+/// Every language glu knows: the built-in ones, plus any added before the
+/// first time this was called.
+pub fn languages() -> &'static [Language] {
+    LANGUAGES.get_or_init(builtin_languages)
+}
+
+/// Know about these languages as well as the built-in ones. This has to
+/// happen before anything looks a language up, and only once.
+pub fn add_languages(user: Vec<Language>) -> Result<(), String> {
+    LANGUAGES
+        .set(with_user_languages(builtin_languages(), user))
+        .map_err(|_| "too late to add languages, they're already in use".to_string())
+}
 
 // ----------------------------------------------------------------------------
 // Base FileType
 // ----------------------------------------------------------------------------
 
-#[derive(PartialEq, Debug, Copy, Clone, EnumIter)]
-pub enum FileType {
-    Asm,
-    Bash,
-    C,
-    CSS,
-    JavaScript,
-    Python,
-    Rust,
-    Unknown,
+/// A language glu knows how to find file names in, or `FileType::UNKNOWN`.
+#[derive(Copy, Clone)]
+pub struct FileType(Option<&'static Language>);
+
+impl FileType {
+    /// Blocks in a language glu doesn't know, or with none at all
+    pub const UNKNOWN: FileType = FileType(None);
+
+    pub(crate) fn language(&self) -> Option<&'static Language> {
+        self.0
+    }
+
+    /// The language's name, like `rust`, or `unknown`
+    pub fn name(&self) -> &'static str {
+        self.0.map_or("unknown", |l| l.name.as_str())
+    }
+
+    /// Every language glu knows, in the order it tries them
+    pub fn all() -> impl Iterator<Item = FileType> {
+        languages().iter().map(|l| FileType(Some(l)))
+    }
+
+    /// The language with this name or alias, for names people type in, where
+    /// a typo shouldn't quietly mean `unknown`. `unknown` itself is fine.
+    pub fn known(name: &str) -> Result<FileType, String> {
+        match FileType::from(name) {
+            ft if ft == FileType::UNKNOWN && name != ft.name() => Err(format!("unknown language {:?}", name)),
+            ft => Ok(ft),
+        }
+    }
+}
+
+impl PartialEq for FileType {
+    fn eq(&self, other: &FileType) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl fmt::Debug for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FileType({})", self.name())
+    }
 }
 
 impl From<&Option<String>> for FileType {
     fn from(item: &Option<String>) -> FileType {
         match &item {
             Some(s) => FileType::from(&s[..]),
-            None => FileType::Unknown,
+            None => FileType::UNKNOWN,
         }
     }
 }
 impl From<&str> for FileType {
     fn from(item: &str) -> FileType {
-        match item {
-            "asm" => FileType::Asm,
-            "bash" => FileType::Bash,
-            "c" => FileType::C,
-            "css" => FileType::CSS,
-            "javascript" => FileType::JavaScript,
-            "js" => FileType::JavaScript,
-            "py" => FileType::Python,
-            "python" => FileType::Python,
-            "python3" => FileType::Python,
-            "rust" => FileType::Rust,
-            "rs" => FileType::Rust,
-            _ => FileType::Unknown,
-        }
+        FileType::all()
+            .find(|ft| ft.name() == item || ft.0.is_some_and(|l| l.aliases.iter().any(|a| a == item)))
+            .unwrap_or(FileType::UNKNOWN)
     }
 }
 
 impl From<FileType> for String {
     fn from(item: FileType) -> String {
-        match item {
-            FileType::Asm => "asm",
-            FileType::Bash => "bash",
-            FileType::C => "c",
-            FileType::CSS => "css",
-            FileType::JavaScript => "javascript",
-            FileType::Python => "python",
-            FileType::Rust => "rust",
-            FileType::Unknown => "unknown",
-        }.into()
+        item.name().into()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use strum::IntoEnumIterator;
 
     #[test]
     fn from_str() {
-        assert_eq!(FileType::from("js"), FileType::JavaScript);
-        assert_eq!(FileType::from("javascript"), FileType::JavaScript);
-        assert_eq!(FileType::from("foo"), FileType::Unknown);
+        assert_eq!(FileType::from("js").name(), "javascript");
+        assert_eq!(FileType::from("javascript"), FileType::from("js"));
+        assert_eq!(FileType::from("python3").name(), "python");
+        assert_eq!(FileType::from("foo"), FileType::UNKNOWN);
+    }
+
+    #[test]
+    fn known() {
+        assert_eq!(FileType::known("py"), Ok(FileType::from("python")));
+        assert_eq!(FileType::known("unknown"), Ok(FileType::UNKNOWN));
+        assert_eq!(FileType::known("pyhton"), Err("unknown language \"pyhton\"".to_string()));
     }
 
     #[test]
     fn from_option_string() {
-        assert_eq!(FileType::from(&Some("js".to_string())), FileType::JavaScript);
-        assert_eq!(FileType::from(&Some("foo".to_string())), FileType::Unknown);
-        assert_eq!(FileType::from(&None), FileType::Unknown);
+        assert_eq!(FileType::from(&Some("js".to_string())).name(), "javascript");
+        assert_eq!(FileType::from(&Some("foo".to_string())), FileType::UNKNOWN);
+        assert_eq!(FileType::from(&None), FileType::UNKNOWN);
     }
 
     #[test]
     fn to_string() {
-        assert_eq!(String::from(FileType::JavaScript), "javascript".to_string());
-        assert_eq!(String::from(FileType::Unknown), "unknown".to_string());
+        assert_eq!(String::from(FileType::from("js")), "javascript".to_string());
+        assert_eq!(String::from(FileType::UNKNOWN), "unknown".to_string());
     }
 
     #[test]
     fn roundtrip() {
-        for variant in FileType::iter() {
+        for variant in FileType::all().chain([FileType::UNKNOWN]) {
             let s = String::from(variant);
             let tripped = FileType::from(s.as_str());
             assert_eq!(tripped, variant);
         }
     }
+
+    #[test]
+    fn builtin_languages() {
+        let languages = parse_languages(BUILTIN).unwrap();
+        assert!(languages.iter().all(|l| !l.name.is_empty() && !l.extensions.is_empty()));
+        for (i, l) in languages.iter().enumerate() {
+            assert!(languages[..i].iter().all(|m| m.name != l.name), "{} is listed twice", l.name);
+        }
+        assert!(languages.iter().all(|l| l.line_comment.is_some() || l.block_comment.is_some()));
+        assert!(parse_languages("[[language]]\nname = \"x\"\ncolour = \"red\"\n").is_err());
+    }
+
+    #[test]
+    fn too_late_to_add() {
+        languages();
+        assert!(add_languages(vec![]).is_err());
+    }
+
+    #[test]
+    fn user_languages() {
+        let user = parse_languages(concat!(
+            "[[language]]\n",
+            "name = \"rust\"\n",
+            "extensions = [\"rs\", \"rs.in\"]\n",
+            "line-comment = \"//\"\n",
+            "[[language]]\n",
+            "name = \"house\"\n",
+            "aliases = [\"py\"]\n",
+            "extensions = [\"hs\"]\n",
+            "line-comment = \"--\"\n",
+        )).unwrap();
+        let languages = with_user_languages(parse_languages(BUILTIN).unwrap(), user);
+        let names: Vec<&str> = languages.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names.iter().filter(|n| **n == "rust").count(), 1);
+        assert_eq!(names[..2], ["rust", "house"]);
+        assert_eq!(languages[0].extensions, vec!["rs", "rs.in"]);
+
+        // The user's alias is found first
+        let lookup = languages.iter().find(|l| l.name == "py" || l.aliases.iter().any(|a| a == "py"));
+        assert_eq!(lookup.map(|l| l.name.as_str()), Some("house"));
+    }
 }
 
 // ----------------------------------------------------------------------------
@@ -104,18 +238,52 @@ pub struct PathDetection {
     pub path: String,
 }
 
-pub fn detect_path(ft: FileType, lines: &Vec<&str>) -> Option<PathDetection> {
-    let pat: &str = match ft {
-        FileType::Asm => r";\s*(\w.*\.(s|asm)\b)",
-        FileType::Bash => r"#\s*(\w.*\.sh\b)",
-        FileType::C => r"//\s*(\w.*\.(c|h)\b)",
-        FileType::CSS => r"/\*\s*(\w.*\.css\b)\s*\*/",
-        FileType::JavaScript => r"//\s*(\w.*\.js\b)",
-        FileType::Python => r"#\s*(\w.*\.py\b)",
-        FileType::Rust => r"//\s*(\w.*\.rs\b)",
-        FileType::Unknown => return None,
+// A regex for a path comment in this language, capturing the path
+fn path_pattern(lang: &Language) -> Option<Regex> {
+    let escape = |names: &Vec<String>| names.iter().map(|n| regex::escape(n)).collect::<Vec<_>>().join("|");
+
+    // Either something ending in one of the extensions, or one of the whole
+    // file names, which mustn't just be the start of a longer one
+    let mut paths: Vec<String> = vec![];
+    if !lang.extensions.is_empty() {
+        paths.push(format!(r"(\.?\w.*\.(?:{})\b)", escape(&lang.extensions)));
+    }
+    if !lang.filenames.is_empty() {
+        paths.push(format!(r"((?:[\w.-]+/)*(?:{}))(?:\s|$)", escape(&lang.filenames)));
+    }
+    if paths.is_empty() {
+        return None;
+    }
+    let path = format!("(?:{})", paths.join("|"));
+
+    // A word like `REM` needs a space after it, or `REMOVE x.bat` names `OVE x.bat`
+    let gap = |opener: &str| match opener.ends_with(|ch: char| ch.is_alphanumeric()) {
+        true => r"\s+",
+        false => r"\s*",
     };
-    let re = Regex::new(pat).expect("Failed to compile regex");
+    let mut forms: Vec<String> = vec![];
+    if let Some(start) = &lang.line_comment {
+        forms.push(format!(r"{}{}{}", regex::escape(start), gap(start), path));
+    }
+    if let Some((open, close)) = &lang.block_comment {
+        forms.push(format!(r"{}{}{}\s*{}", regex::escape(open), gap(open), path, regex::escape(close)));
+    }
+    match forms.is_empty() {
+        true => None,
+        false => Some(Regex::new(&forms.join("|")).expect("Failed to compile regex")),
+    }
+}
+
+// Compiled once per language, in the same order as languages()
+fn cached_path_pattern(lang: &'static Language) -> Option<&'static Regex> {
+    static PATTERNS: OnceLock<Vec<Option<Regex>>> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| languages().iter().map(path_pattern).collect());
+    let i = languages().iter().position(|l| ptr::eq(l, lang))?;
+    patterns[i].as_ref()
+}
+
+pub fn detect_path(ft: FileType, lines: &Vec<&str>) -> Option<PathDetection> {
+    let re = cached_path_pattern(ft.language()?)?;
 
     lines
         .iter()
@@ -125,7 +293,54 @@ pub fn detect_path(ft: FileType, lines: &Vec<&str>) -> Option<PathDetection> {
         .find(|(_, cap)| cap.is_some())
         .map(|(count, cap)| PathDetection {
             line_number: count,
-            path: cap.unwrap().get(1).unwrap().as_str().to_string(),
+            // Whichever comment form matched
+            path: cap.unwrap().iter().skip(1).flatten().next().unwrap().as_str().to_string(),
+        })
+}
+
+// Every comment style glu knows, around nothing but a path. Stricter than
+// path_pattern, since with no language to go on, any `#` or `//` line in a
+// plain block could be one.
+fn any_path_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        let mut names: Vec<String> = languages().iter().flat_map(|l| l.filenames.iter()).map(|n| regex::escape(n)).collect();
+        names.sort();
+        names.dedup();
+        let path = format!(r"((?:[\w.-]+/)*(?:[\w.-]*\w\.[A-Za-z]\w*|{}))", names.join("|"));
+
+        let mut lines: Vec<String> = languages().iter().flat_map(|l| &l.line_comment).map(|c| regex::escape(c)).collect();
+        let mut blocks: Vec<String> = languages()
+            .iter()
+            .flat_map(|l| &l.block_comment)
+            .map(|(open, close)| format!(r"^\s*{}\s*{}\s*{}\s*$", regex::escape(open), path, regex::escape(close)))
+            .collect();
+        // Longest first, so `;;` isn't taken for `;` and a stray `;`
+        lines.sort_by_key(|c| std::cmp::Reverse(c.len()));
+        lines.dedup();
+        blocks.sort();
+        blocks.dedup();
+
+        let mut forms = vec![format!(r"^\s*(?:{})\s*{}\s*$", lines.join("|"), path)];
+        forms.extend(blocks);
+        Regex::new(&forms.join("|")).expect("Failed to compile regex")
+    })
+}
+
+// Like detect_path, for blocks with no language: a path in any known comment
+// style. The path's extension is all there is to say what language it is.
+pub fn guess_path(lines: &[&str]) -> Option<PathDetection> {
+    let re = any_path_pattern();
+    lines
+        .iter()
+        .take(3)
+        .enumerate()
+        .find_map(|(count, line)| {
+            let cap = re.captures(line)?;
+            Some(PathDetection {
+                line_number: count,
+                path: cap.iter().skip(1).flatten().next()?.as_str().to_string(),
+            })
         })
 }
 
@@ -147,7 +362,7 @@ mod test {
 
     #[test]
     fn test_asm() {
-        let ft = FileType::Asm;
+        let ft = FileType::from("asm");
         check_none(ft, vec![]);
         check_none(ft, vec![
             ".intel_syntax noprefix",
@@ -167,7 +382,7 @@ mod test {
 
     #[test]
     fn test_bash() {
-        let ft = FileType::Bash;
+        let ft = FileType::from("bash");
         check_none(ft, vec![]);
         check_none(ft, vec![
             "#!/bin/bash",
@@ -191,7 +406,7 @@ mod test {
 
     #[test]
     fn test_c() {
-        let ft = FileType::C;
+        let ft = FileType::from("c");
         check_none(ft, vec![]);
         check_none(ft, vec![
             "#define FOO 1",
@@ -213,7 +428,7 @@ mod test {
 
     #[test]
     fn test_css() {
-        let ft = FileType::CSS;
+        let ft = FileType::from("css");
         check_none(ft, vec![]);
         check_none(ft, vec![
             "body { margin: 0px }",
@@ -227,7 +442,7 @@ mod test {
 
     #[test]
     fn test_python() {
-        let ft = FileType::Python;
+        let ft = FileType::from("python");
         check_none(ft, vec![
             "print(123)",
         ]);
@@ -240,7 +455,7 @@ mod test {
 
     #[test]
     fn test_rust() {
-        let ft = FileType::Rust;
+        let ft = FileType::from("rust");
         check_none(ft, vec![
             "fn main() {",
             "    println!('Hello world');",
@@ -257,7 +472,7 @@ mod test {
 
     #[test]
     fn test_js() {
-        let ft = FileType::JavaScript;
+        let ft = FileType::from("javascript");
         check_none(ft, vec![]);
         check_none(ft, vec![
             "function foo() {",
@@ -299,10 +514,387 @@ mod test {
         ]);
     }
 
+    // Fence language, block, and where the path comment is and what it says
+    type Case = (&'static str, &'static [&'static str], Option<(usize, &'static str)>);
+
+    const CASES: &[Case] = &[
+        ("cpp", &["#include <iostream>"], None),
+        ("cpp", &["// main.cpp", "", "#include <iostream>"], Some((0, "main.cpp"))),
+        ("csharp", &["using System;"], None),
+        ("csharp", &["// Program.cs", "", "using System;"], Some((0, "Program.cs"))),
+        ("scss", &["$pad: 4px;"], None),
+        ("scss", &["// site.scss", "$pad: 4px;"], Some((0, "site.scss"))),
+        ("scss", &["/* theme.scss */", "$pad: 4px;"], Some((0, "theme.scss"))),
+        ("sass", &["$pad: 4px"], None),
+        ("sass", &["// site.sass", "$pad: 4px"], Some((0, "site.sass"))),
+        ("less", &["@pad: 4px;"], None),
+        ("less", &["// site.less", "@pad: 4px;"], Some((0, "site.less"))),
+        ("javascript", &["export default 1;"], None),
+        ("javascript", &["// index.mjs", "export default 1;"], Some((0, "index.mjs"))),
+        ("javascript", &["#!/usr/bin/env node", "// cli.cjs", "module.exports = 1;"], Some((1, "cli.cjs"))),
+        ("jsx", &["const App = () => <div />;"], None),
+        ("jsx", &["// App.jsx", "", "const App = () => <div />;"], Some((0, "App.jsx"))),
+        ("typescript", &["let x: number = 1;"], None),
+        ("typescript", &["// index.ts", "", "let x: number = 1;"], Some((0, "index.ts"))),
+        ("typescript", &["/* types.d.ts */", "declare const x: number;"], Some((0, "types.d.ts"))),
+        ("tsx", &["const App = (): JSX.Element => <div />;"], None),
+        ("tsx", &["// App.tsx", "const App = (): JSX.Element => <div />;"], Some((0, "App.tsx"))),
+        ("coffeescript", &["square = (x) -> x * x"], None),
+        ("coffeescript", &["# math.coffee", "square = (x) -> x * x"], Some((0, "math.coffee"))),
+        ("json", &["{ \"compilerOptions\": {} }"], None),
+        ("json", &["// tsconfig.json", "{ \"compilerOptions\": {} }"], Some((0, "tsconfig.json"))),
+        ("json", &["/* settings.jsonc */", "{}"], Some((0, "settings.jsonc"))),
+        ("ruby", &["puts 'hello'"], None),
+        ("ruby", &["# hello.rb", "puts 'hello'"], Some((0, "hello.rb"))),
+        ("perl", &["print \"hello\\n\";"], None),
+        ("perl", &["#!/usr/bin/perl", "# hello.pl", "print \"hello\\n\";"], Some((1, "hello.pl"))),
+        ("php", &["<?php", "echo 'hi';"], None),
+        ("php", &["<?php", "// index.php", "echo 'hi';"], Some((1, "index.php"))),
+        ("php", &["<?php", "/* lib.php */"], Some((1, "lib.php"))),
+        ("lua", &["print('hi')"], None),
+        ("lua", &["-- init.lua", "print('hi')"], Some((0, "init.lua"))),
+        ("r", &["x <- c(1, 2, 3)"], None),
+        ("r", &["# analysis.R", "x <- c(1, 2, 3)"], Some((0, "analysis.R"))),
+        ("julia", &["println(\"hi\")"], None),
+        ("julia", &["# main.jl", "println(\"hi\")"], Some((0, "main.jl"))),
+        ("julia", &["#= util.jl =#", "f(x) = x"], Some((0, "util.jl"))),
+        ("tcl", &["puts hello"], None),
+        ("tcl", &["# hello.tcl", "puts hello"], Some((0, "hello.tcl"))),
+        ("awk", &["{ print $1 }"], None),
+        ("awk", &["# first.awk", "{ print $1 }"], Some((0, "first.awk"))),
+        ("powershell", &["Write-Host 'hi'"], None),
+        ("powershell", &["# build.ps1", "Write-Host 'hi'"], Some((0, "build.ps1"))),
+        ("powershell", &["<# Tools.psm1 #>", "function Get-Tool {}"], Some((0, "Tools.psm1"))),
+        ("batch", &["@echo off"], None),
+        ("go", &["package main"], None),
+        ("go", &["// main.go", "", "package main"], Some((0, "main.go"))),
+        ("go", &[
+            "// Server entry point",
+            "// cmd/server/main.go",
+            "package main",
+        ], Some((1, "cmd/server/main.go"))),
+        ("zig", &["const std = @import(\"std\");"], None),
+        ("zig", &["// main.zig", "const std = @import(\"std\");"], Some((0, "main.zig"))),
+        ("d", &["import std.stdio;"], None),
+        ("d", &["// app.d", "import std.stdio;"], Some((0, "app.d"))),
+        ("nim", &["echo \"hi\""], None),
+        ("nim", &["# hello.nim", "echo \"hi\""], Some((0, "hello.nim"))),
+        ("crystal", &["puts \"hi\""], None),
+        ("crystal", &["# hello.cr", "puts \"hi\""], Some((0, "hello.cr"))),
+        ("java", &["public class Main {}"], None),
+        ("java", &["// src/Main.java", "public class Main {}"], Some((0, "src/Main.java"))),
+        ("java", &["/* Util.java */", "class Util {}"], Some((0, "Util.java"))),
+        ("kotlin", &["fun main() {}"], None),
+        ("kotlin", &["// Main.kt", "fun main() {}"], Some((0, "Main.kt"))),
+        ("kotlin", &["// build.gradle.kts", "plugins {}"], Some((0, "build.gradle.kts"))),
+        ("scala", &["object Main extends App"], None),
+        ("scala", &["// Main.scala", "object Main extends App"], Some((0, "Main.scala"))),
+        ("groovy", &["apply plugin: 'java'"], None),
+        ("groovy", &["// build.gradle", "apply plugin: 'java'"], Some((0, "build.gradle"))),
+        ("clojure", &["(ns app.core)"], None),
+        ("clojure", &["; src/app/core.clj", "(ns app.core)"], Some((0, "src/app/core.clj"))),
+        ("swift", &["print(\"hi\")"], None),
+        ("swift", &["// main.swift", "print(\"hi\")"], Some((0, "main.swift"))),
+        ("objectivec", &["#import <Foundation/Foundation.h>"], None),
+        ("objectivec", &["// main.m", "#import <Foundation/Foundation.h>"], Some((0, "main.m"))),
+        ("dart", &["void main() {}"], None),
+        ("dart", &["// lib/main.dart", "void main() {}"], Some((0, "lib/main.dart"))),
+        ("haskell", &["main = putStrLn \"hi\""], None),
+        ("haskell", &["-- Main.hs", "main = putStrLn \"hi\""], Some((0, "Main.hs"))),
+        ("haskell", &["{- Lib.hs -}", "module Lib where"], Some((0, "Lib.hs"))),
+        ("elm", &["module Main exposing (main)"], None),
+        ("elm", &["-- src/Main.elm", "module Main exposing (main)"], Some((0, "src/Main.elm"))),
+        ("purescript", &["module Main where"], None),
+        ("purescript", &["-- src/Main.purs", "module Main where"], Some((0, "src/Main.purs"))),
+        ("fsharp", &["printfn \"hi\""], None),
+        ("fsharp", &["// Program.fs", "printfn \"hi\""], Some((0, "Program.fs"))),
+        ("fsharp", &["(* Lib.fsi *)", "module Lib"], Some((0, "Lib.fsi"))),
+        ("erlang", &["-module(hello)."], None),
+        ("erlang", &["% hello.erl", "-module(hello)."], Some((0, "hello.erl"))),
+        ("elixir", &["IO.puts \"hi\""], None),
+        ("elixir", &["# lib/app.ex", "defmodule App do", "end"], Some((0, "lib/app.ex"))),
+        ("elixir", &["# test/app_test.exs", "ExUnit.start()"], Some((0, "test/app_test.exs"))),
+        ("lisp", &["(defun hi () (print \"hi\"))"], None),
+        ("scheme", &["(display \"hi\")"], None),
+        ("scheme", &["; hi.scm", "(display \"hi\")"], Some((0, "hi.scm"))),
+        ("racket", &["#lang racket"], None),
+        ("racket", &["#lang racket", "; hi.rkt", "(displayln \"hi\")"], Some((1, "hi.rkt"))),
+        ("fortran", &["program hello"], None),
+        ("fortran", &["! hello.f90", "program hello"], Some((0, "hello.f90"))),
+        ("pascal", &["program Hello;"], None),
+        ("pascal", &["// hello.pas", "program Hello;"], Some((0, "hello.pas"))),
+        ("pascal", &["{ unit1.pas }", "unit Unit1;"], Some((0, "unit1.pas"))),
+        ("ada", &["with Ada.Text_IO;"], None),
+        ("ada", &["-- hello.adb", "with Ada.Text_IO;"], Some((0, "hello.adb"))),
+        ("matlab", &["x = linspace(0, 1);"], None),
+        ("matlab", &["% plot_it.m", "x = linspace(0, 1);"], Some((0, "plot_it.m"))),
+        ("verilog", &["module top;"], None),
+        ("verilog", &["// top.v", "module top;"], Some((0, "top.v"))),
+        ("verilog", &["/* bus.sv */", "interface bus;"], Some((0, "bus.sv"))),
+        ("vhdl", &["entity top is"], None),
+        ("vhdl", &["-- top.vhd", "entity top is"], Some((0, "top.vhd"))),
+        ("solidity", &["pragma solidity ^0.8.0;"], None),
+        ("solidity", &["// Token.sol", "pragma solidity ^0.8.0;"], Some((0, "Token.sol"))),
+        ("glsl", &["void main() {}"], None),
+        ("glsl", &["// shader.frag", "void main() {}"], Some((0, "shader.frag"))),
+        ("wasm", &["(module)"], None),
+        ("wasm", &["(; sub.wat ;)", "(module)"], Some((0, "sub.wat"))),
+        ("sql", &["SELECT 1;"], None),
+        ("sql", &["-- schema.sql", "CREATE TABLE t (id int);"], Some((0, "schema.sql"))),
+        ("sql", &["/* seed.sql */", "INSERT INTO t VALUES (1);"], Some((0, "seed.sql"))),
+        ("graphql", &["type Query { hi: String }"], None),
+        ("graphql", &["# schema.graphql", "type Query { hi: String }"], Some((0, "schema.graphql"))),
+        ("protobuf", &["syntax = \"proto3\";"], None),
+        ("protobuf", &["// api.proto", "syntax = \"proto3\";"], Some((0, "api.proto"))),
+        ("yaml", &["name: CI"], None),
+        ("yaml", &["# .github/workflows/ci.yml", "name: CI"], Some((0, ".github/workflows/ci.yml"))),
+        ("yaml", &["# config.yaml", "debug: true"], Some((0, "config.yaml"))),
+        ("toml", &["[package]"], None),
+        ("toml", &["# Cargo.toml", "[package]"], Some((0, "Cargo.toml"))),
+        ("ini", &["[section]"], None),
+        ("ini", &["; setup.cfg", "[metadata]"], Some((0, "setup.cfg"))),
+        ("properties", &["key=value"], None),
+        ("properties", &["# app.properties", "key=value"], Some((0, "app.properties"))),
+        ("hcl", &["provider \"aws\" {}"], None),
+        ("hcl", &["# main.tf", "provider \"aws\" {}"], Some((0, "main.tf"))),
+        ("hcl", &["/* vars.tfvars */", "region = \"eu\""], Some((0, "vars.tfvars"))),
+        ("nix", &["{ pkgs ? import <nixpkgs> {} }:"], None),
+        ("nix", &["# shell.nix", "{ pkgs ? import <nixpkgs> {} }:"], Some((0, "shell.nix"))),
+        ("nix", &["/* flake.nix */", "{ outputs = _: {}; }"], Some((0, "flake.nix"))),
+        ("makefile", &["all:", "\tcc main.c"], None),
+        ("makefile", &["# rules.mk", "CC = cc"], Some((0, "rules.mk"))),
+        ("cmake", &["project(app)"], None),
+        ("cmake", &["# cmake/deps.cmake", "find_package(Foo)"], Some((0, "cmake/deps.cmake"))),
+        ("dockerfile", &["FROM alpine"], None),
+        ("nginx", &["server { listen 80; }"], None),
+        ("vim", &["set number"], None),
+        ("vim", &["\" plugin/foo.vim", "let g:foo = 1"], Some((0, "plugin/foo.vim"))),
+        ("latex", &["\\documentclass{article}"], None),
+        ("latex", &["% paper.tex", "\\documentclass{article}"], Some((0, "paper.tex"))),
+    ];
+
+    #[test]
+    fn test_languages() {
+        for (lang, lines, expected) in CASES {
+            let ft = FileType::from(*lang);
+            assert_ne!(ft, FileType::UNKNOWN, "{}", lang);
+            let found = detect_path(ft, &lines.to_vec()).map(|pd| (pd.line_number, pd.path));
+            assert_eq!(found, expected.map(|(n, p)| (n, p.to_string())), "{} {:?}", lang, lines);
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        for ft in FileType::all() {
+            check_none(ft, vec![]);
+        }
+    }
+
+    #[test]
+    fn test_filenames() {
+        check_some(FileType::from("makefile"), 0, "Makefile", vec![
+            "# Makefile",
+            "all:",
+            "\tcc main.c",
+        ]);
+        check_some(FileType::from("makefile"), 0, "sub/GNUmakefile", vec!["# sub/GNUmakefile", "all:"]);
+        check_some(FileType::from("cmake"), 0, "CMakeLists.txt", vec!["# CMakeLists.txt", "project(app)"]);
+        check_some(FileType::from("dockerfile"), 0, "Dockerfile", vec!["# Dockerfile", "FROM alpine"]);
+        check_some(FileType::from("dockerfile"), 0, "web/Containerfile", vec!["# web/Containerfile", "FROM alpine"]);
+        check_some(FileType::from("ruby"), 0, "Gemfile", vec!["# Gemfile", "source 'https://rubygems.org'"]);
+        check_some(FileType::from("nginx"), 0, "nginx.conf", vec!["# nginx.conf", "server { listen 80; }"]);
+        check_some(FileType::from("ini"), 0, ".editorconfig", vec!["; .editorconfig", "root = true"]);
+        check_some(FileType::from("vim"), 0, ".vimrc", vec!["\" .vimrc", "set number"]);
+
+        // Only the whole name counts
+        check_none(FileType::from("makefile"), vec!["# NotAMakefile", "all:"]);
+        check_none(FileType::from("dockerfile"), vec!["# Dockerfile.old", "FROM alpine"]);
+    }
+
+    #[test]
+    fn test_block_comments_only() {
+        let html = FileType::from("html");
+        check_none(html, vec!["<!DOCTYPE html>", "<p>Hello</p>"]);
+        check_some(html, 1, "index.html", vec![
+            "<!DOCTYPE html>",
+            "<!-- index.html -->",
+            "<p>Hello</p>",
+        ]);
+        check_some(html, 0, "about.htm", vec!["<!--about.htm-->", "<p>About</p>"]);
+        check_none(html, vec!["// index.html", "<p>Hello</p>"]);
+
+        let xml = FileType::from("xml");
+        check_none(xml, vec!["<?xml version=\"1.0\"?>", "<root/>"]);
+        check_some(xml, 1, "pom.xml", vec![
+            "<?xml version=\"1.0\"?>",
+            "<!-- pom.xml -->",
+            "<project/>",
+        ]);
+        check_some(xml, 0, "icons/logo.svg", vec!["<!-- icons/logo.svg -->", "<svg/>"]);
+
+        check_some(FileType::from("vue"), 0, "App.vue", vec!["<!-- App.vue -->", "<template><p>Hi</p></template>"]);
+        check_some(FileType::from("svelte"), 0, "App.svelte", vec!["<!-- App.svelte -->", "<h1>Hello {name}!</h1>"]);
+        check_some(FileType::from("markdown"), 0, "docs/intro.md", vec!["<!-- docs/intro.md -->", "# Title"]);
+        check_none(FileType::from("markdown"), vec!["# docs/intro.md"]);
+
+        let ocaml = FileType::from("ocaml");
+        check_none(ocaml, vec!["let () = print_endline \"hi\""]);
+        check_some(ocaml, 0, "main.ml", vec!["(* main.ml *)", "let () = print_endline \"hi\""]);
+        check_none(ocaml, vec!["(* main.ml", "let () = print_endline \"hi\""]);
+    }
+
+    #[test]
+    fn test_multichar_openers() {
+        check_some(FileType::from("clojure"), 0, "deps.edn", vec![";; deps.edn", "{:deps {}}"]);
+        check_some(FileType::from("lisp"), 0, "hi.lisp", vec![";; hi.lisp", "(defun hi () (print \"hi\"))"]);
+        check_some(FileType::from("lisp"), 0, "init.el", vec![";;; init.el", "(setq x 1)"]);
+        check_some(FileType::from("wasm"), 0, "add.wat", vec![";; add.wat", "(module)"]);
+        check_some(FileType::from("batch"), 1, "build.bat", vec!["@echo off", "REM build.bat", "echo hi"]);
+        check_none(FileType::from("batch"), vec!["REMOVE build.bat"]);
+        check_some(FileType::from("lua"), 0, "conf.lua", vec!["--[[ conf.lua ]]", "print('hi')"]);
+    }
+
+    #[test]
+    fn test_headers() {
+        let (c, cpp) = (FileType::from("c"), FileType::from("cpp"));
+        check_some(cpp, 0, "vec.hpp", vec!["/* vec.hpp */", "#pragma once"]);
+        check_none(c, vec!["/* vec.hpp */", "#pragma once"]);
+        check_none(cpp, vec!["// vec.h", "#pragma once"]);
+        assert_eq!(FileType::from_path("vec.h"), c);
+        assert_eq!(FileType::from_path("vec.hpp"), cpp);
+    }
+
+    #[test]
+    fn test_guess() {
+        let guess = |lines: Vec<&str>| guess_path(&lines).map(|pd| (pd.line_number, pd.path));
+        assert_eq!(guess(vec![]), None);
+        assert_eq!(guess(vec!["# config.yaml", "debug: true"]), Some((0, "config.yaml".to_string())));
+        assert_eq!(guess(vec!["// util.go", "package util"]), Some((0, "util.go".to_string())));
+        assert_eq!(guess(vec!["#!/bin/sh", "# scripts/run.sh"]), Some((1, "scripts/run.sh".to_string())));
+        assert_eq!(guess(vec!["<!-- index.html -->"]), Some((0, "index.html".to_string())));
+        assert_eq!(guess(vec!["-- schema.sql"]), Some((0, "schema.sql".to_string())));
+        assert_eq!(guess(vec![";; add.wat"]), Some((0, "add.wat".to_string())));
+        assert_eq!(guess(vec!["# Makefile"]), Some((0, "Makefile".to_string())));
+        assert_eq!(guess(vec!["# .github/workflows/ci.yml"]), Some((0, ".github/workflows/ci.yml".to_string())));
+
+        // Prose and numbers aren't paths
+        check_none_guess(vec!["# Install the deps first, see setup.py"]);
+        check_none_guess(vec!["// version 1.2", "// e.g.", "#!/usr/bin/env python3"]);
+        check_none_guess(vec!["line one", "line two", "line three", "# late.py"]);
+    }
+
+    fn check_none_guess(lines: Vec<&str>) {
+        assert_eq!(guess_path(&lines), None);
+    }
+
     #[test]
     fn test_unknown() {
-        let ft = FileType::Unknown;
+        let ft = FileType::UNKNOWN;
         check_none(ft, vec![]);
         check_none(ft, vec!["Anything"]);
     }
 }
+
+// ----------------------------------------------------------------------------
+// Reverse mapping, for packing files back into Markdown
+// ----------------------------------------------------------------------------
+
+impl FileType {
+    // Extensions detect_path accepts for this type, the usual one first
+    pub(crate) fn extensions(&self) -> Vec<&'static str> {
+        self.0.iter().flat_map(|l| l.extensions.iter()).map(|e| e.as_str()).collect()
+    }
+
+    pub(crate) fn from_extension(ext: &str) -> FileType {
+        FileType::all()
+            .find(|ft| ft.extensions().contains(&ext))
+            .unwrap_or(FileType::UNKNOWN)
+    }
+
+    // Whole file names detect_path accepts for this type, like `Makefile`
+    pub(crate) fn filenames(&self) -> Vec<&'static str> {
+        self.0.iter().flat_map(|l| l.filenames.iter()).map(|f| f.as_str()).collect()
+    }
+
+    // By whole file name first, so `CMakeLists.txt` isn't plain text
+    pub(crate) fn from_path(path: &str) -> FileType {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        FileType::all()
+            .find(|ft| ft.filenames().contains(&file_name))
+            .or_else(|| file_name.rsplit_once('.').map(|(_, ext)| FileType::from_extension(ext)))
+            .unwrap_or(FileType::UNKNOWN)
+    }
+
+    // The language a shebang's interpreter runs, e.g. `python3`
+    pub(crate) fn from_interpreter(program: &str) -> FileType {
+        FileType::all()
+            .find(|ft| ft.0.is_some_and(|l| l.interpreters.iter().any(|i| i == program)))
+            .unwrap_or(FileType::UNKNOWN)
+    }
+
+    // A comment naming the path, in the form detect_path looks for
+    pub(crate) fn path_comment(&self, path: &str) -> Option<String> {
+        let lang = self.0?;
+        match (&lang.line_comment, &lang.block_comment) {
+            (Some(start), _) => Some(format!("{} {}", start, path)),
+            (None, Some((open, close))) => Some(format!("{} {} {}", open, path, close)),
+            (None, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod reverse_tests {
+    use super::*;
+
+    #[test]
+    fn from_extension() {
+        assert_eq!(FileType::from_extension("js"), FileType::from("javascript"));
+        assert_eq!(FileType::from_extension("h"), FileType::from("c"));
+        assert_eq!(FileType::from_extension("asm"), FileType::from("asm"));
+        assert_eq!(FileType::from_extension("txt"), FileType::UNKNOWN);
+        assert_eq!(FileType::from_extension(""), FileType::UNKNOWN);
+    }
+
+    #[test]
+    fn from_path() {
+        assert_eq!(FileType::from_path("src/main.go"), FileType::from("go"));
+        assert_eq!(FileType::from_path("Makefile"), FileType::from("makefile"));
+        assert_eq!(FileType::from_path("docker/Dockerfile"), FileType::from("dockerfile"));
+        assert_eq!(FileType::from_path("CMakeLists.txt"), FileType::from("cmake"));
+        assert_eq!(FileType::from_path("notes.txt"), FileType::UNKNOWN);
+        assert_eq!(FileType::from_path("LICENSE"), FileType::UNKNOWN);
+    }
+
+    #[test]
+    fn from_interpreter() {
+        assert_eq!(FileType::from_interpreter("python3"), FileType::from("python"));
+        assert_eq!(FileType::from_interpreter("sh"), FileType::from("bash"));
+        assert_eq!(FileType::from_interpreter("perl6"), FileType::UNKNOWN);
+    }
+
+    #[test]
+    fn path_comment_roundtrip() {
+        for ft in FileType::all() {
+            for ext in ft.extensions() {
+                let path = format!("dir/file.{}", ext);
+                let comment = ft.path_comment(&path).unwrap();
+                assert_eq!(detect_path(ft, &vec![comment.as_str()]), Some(PathDetection {
+                    line_number: 0,
+                    path: path,
+                }));
+            }
+            for name in ft.filenames() {
+                let path = format!("dir/{}", name);
+                let comment = ft.path_comment(&path).unwrap();
+                assert_eq!(detect_path(ft, &vec![comment.as_str()]), Some(PathDetection {
+                    line_number: 0,
+                    path: path.clone(),
+                }));
+                assert_eq!(FileType::from_path(&path), ft);
+            }
+        }
+        assert_eq!(FileType::UNKNOWN.path_comment("foo"), None);
+    }
+}
+
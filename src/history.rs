@@ -0,0 +1,142 @@
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use crate::merge::{Conflict, merge};
+use crate::segment::Segment;
+use crate::writer::write_files;
+
+// ----------------------------------------------------------------------------
+// Writing a tutorial into a git repository, one commit per section
+// ----------------------------------------------------------------------------
+//
+// Each section's commit holds the project as it stands at the end of that
+// section, with the heading as the message, tagged `step-N` so readers can
+// check out any point in the tutorial. Blocks before the first section go in
+// a commit of their own, tagged `step-0`.
+
+fn git(dir: &Path, args: &[&str]) -> io::Result<bool> {
+    let status = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .status()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run git: {}", e)))?;
+    Ok(status.success())
+}
+
+// Like git(), but failing is an error
+fn git_ok(dir: &Path, args: &[&str]) -> io::Result<()> {
+    match git(dir, args)? {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("git {} failed", args.join(" ")))),
+    }
+}
+
+fn section_number(segment: &Segment) -> usize {
+    segment.section.as_ref().map_or(0, |s| s.number)
+}
+
+// The section numbers that have blocks in them, with their commit messages
+fn sections(blocks: &[Segment]) -> Vec<(usize, String)> {
+    let mut sections: Vec<(usize, String)> = vec![];
+    for block in blocks {
+        let number = section_number(block);
+        if sections.iter().all(|(n, _)| *n != number) {
+            let title = block.section.as_ref().map_or("Initial files".to_string(), |s| s.title.clone());
+            sections.push((number, title));
+        }
+    }
+    sections.sort_by_key(|(n, _)| *n);
+    sections
+}
+
+/// Make root a new git repository with a commit per section, and return the
+/// tags that were made. Blocks should have their front matter applied, but
+/// not be merged yet.
+pub fn write_history(root: &Path, blocks: &[Segment], conflict: Conflict) -> io::Result<Vec<String>> {
+    git_ok(root, &["init", "--quiet"])?;
+
+    // Whoever's running glu is the author, if git knows who that is
+    let mut identity: Vec<&str> = vec![];
+    if !git(root, &["config", "user.name"])? || !git(root, &["config", "user.email"])? {
+        identity = vec!["-c", "user.name=glu", "-c", "user.email=glu@localhost"];
+    }
+
+    let mut tags = vec![];
+    let mut next = 0;
+    for (number, title) in sections(blocks) {
+        // Sections with no code in them are the same as the one before
+        for skipped in next..number {
+            tag_head(root, skipped, &mut tags)?;
+        }
+        next = number + 1;
+
+        let so_far: Vec<Segment> = blocks.iter().filter(|b| section_number(b) <= number).cloned().collect();
+        let (files, _) = merge(so_far, conflict).map_err(|e| io::Error::other(e.to_string()))?;
+        write_files(root, &files)?;
+        git_ok(root, &["add", "--all"])?;
+
+        // A section that doesn't change anything still gets its tag
+        if !git(root, &["diff", "--cached", "--quiet"])? {
+            let commit = [identity.as_slice(), &["commit", "--quiet", "--message", &title]].concat();
+            git_ok(root, &commit)?;
+        }
+        tag_head(root, number, &mut tags)?;
+    }
+    Ok(tags)
+}
+
+// Tag the latest commit for a section, if there is one yet
+fn tag_head(root: &Path, number: usize, tags: &mut Vec<String>) -> io::Result<()> {
+    if git(root, &["rev-parse", "--quiet", "--verify", "HEAD"])? {
+        let tag = format!("step-{}", number);
+        git_ok(root, &["tag", &tag])?;
+        tags.push(tag);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+    use crate::parse::parse;
+
+    const DOC: &str = "# Tutorial\n\n```python title=app.py\nprint(1)\n```\n\n\
+        ## Step 1: Greet\n\n```python title=app.py\nprint('hi')\n```\n\n\
+        ## Step 2: Test\n\n```python title=app.py\nprint('hi')\n```\n\n\
+        ```python title=test.py\nimport app\n```\n\n\
+        ## Step 3: Think about it\n\nNo code here.\n\n\
+        ## Step 4: Done\n\n```python title=app.py\nprint('bye')\n```\n";
+
+    fn output(dir: &Path, args: &[&str]) -> String {
+        let out = Command::new("git").args(args).current_dir(dir).output().unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    }
+
+    #[test]
+    fn section_list() {
+        assert_eq!(sections(&parse(DOC)), vec![
+            (0, "Initial files".to_string()),
+            (1, "Step 1: Greet".to_string()),
+            (2, "Step 2: Test".to_string()),
+            (4, "Step 4: Done".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn commits() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let tags = write_history(tmp.path(), &parse(DOC), Conflict::LastWins).unwrap();
+        assert_eq!(tags, vec!["step-0", "step-1", "step-2", "step-3", "step-4"]);
+        assert_eq!(
+            output(tmp.path(), &["log", "--format=%s"]),
+            "Step 4: Done\nStep 2: Test\nStep 1: Greet\nInitial files\n",
+        );
+        assert_eq!(output(tmp.path(), &["show", "step-0:app.py"]), "print(1)\n");
+        assert_eq!(output(tmp.path(), &["show", "step-1:app.py"]), "print('hi')\n");
+        assert_eq!(output(tmp.path(), &["ls-tree", "--name-only", "step-1"]), "app.py\n");
+        assert_eq!(output(tmp.path(), &["ls-tree", "--name-only", "step-2"]), "app.py\ntest.py\n");
+        assert_eq!(output(tmp.path(), &["rev-parse", "step-3^{commit}"]), output(tmp.path(), &["rev-parse", "step-2^{commit}"]));
+    }
+}
@@ -0,0 +1,121 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::download::is_url;
+
+// ----------------------------------------------------------------------------
+// Finding Markdown documents on disk
+// ----------------------------------------------------------------------------
+
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "md" || ext == "markdown")
+}
+
+// Every Markdown file under dir, walked in sorted order so chapter files named
+// 01-..., 02-... come out in reading order.
+pub fn markdown_files<R: AsRef<Path>>(dir: R) -> io::Result<Vec<PathBuf>> {
+    let mut found = vec![];
+    walk(dir.as_ref(), &mut found)?;
+    Ok(found)
+}
+
+fn has_glob(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+// Turn one command line input into the documents it names. URLs and plain
+// files name themselves, directories are walked, and globs are matched in
+// sorted order.
+pub fn expand(input: &str) -> io::Result<Vec<String>> {
+    let path = Path::new(input);
+    if is_url(input) || path.is_file() {
+        return Ok(vec![input.to_string()]);
+    }
+    if path.is_dir() {
+        return Ok(markdown_files(path)?.iter().map(|p| p.to_string_lossy().into_owned()).collect());
+    }
+    if has_glob(input) {
+        let invalid = |e: glob::PatternError| io::Error::new(io::ErrorKind::InvalidInput, e.to_string());
+        let mut found: Vec<String> = glob::glob(input)
+            .map_err(invalid)?
+            .filter_map(Result::ok)
+            .filter(|p| p.is_file())
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        found.sort();
+        return match found.is_empty() {
+            true => Err(io::Error::new(io::ErrorKind::NotFound, "no files match")),
+            false => Ok(found),
+        };
+    }
+    // Let reading it produce the error
+    Ok(vec![input.to_string()])
+}
+
+fn walk(dir: &Path, output_buf: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, output_buf)?;
+        } else if is_markdown(&path) {
+            output_buf.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_markdown_files() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        for name in ["02-server.md", "01-setup.md", "notes.txt", "extra/03-deploy.markdown", "extra/img.png"] {
+            let path = tmp.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let found: Vec<PathBuf> = markdown_files(tmp.path())
+            .unwrap()
+            .iter()
+            .map(|p| p.strip_prefix(tmp.path()).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(found, vec![
+            PathBuf::from("01-setup.md"),
+            PathBuf::from("02-server.md"),
+            PathBuf::from("extra/03-deploy.markdown"),
+        ]);
+    }
+
+    #[test]
+    fn test_expand() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        for name in ["b.md", "a.md", "c.txt", "sub/d.md"] {
+            let path = tmp.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let base = tmp.path().to_string_lossy().into_owned();
+        let names = |input: &str| -> Vec<String> {
+            expand(input)
+                .unwrap()
+                .iter()
+                .map(|p| p.strip_prefix(&base).unwrap_or(p).to_string())
+                .collect()
+        };
+
+        assert_eq!(names("https://example.com/doc.md"), vec!["https://example.com/doc.md"]);
+        assert_eq!(names(&format!("{}/b.md", base)), vec!["/b.md"]);
+        assert_eq!(names(&base), vec!["/a.md", "/b.md", "/sub/d.md"]);
+        assert_eq!(names(&format!("{}/*.md", base)), vec!["/a.md", "/b.md"]);
+        assert_eq!(names(&format!("{}/missing.md", base)), vec!["/missing.md"]);
+        assert!(expand(&format!("{}/*.rs", base)).is_err());
+    }
+}
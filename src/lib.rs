@@ -0,0 +1,216 @@
+//! Unpack a Markdown document full of code blocks into a tree of files.
+//!
+//! This is the library behind the `glu` command. Most callers only need
+//! [`Options`]: parse a document's text, then look at the files it describes,
+//! collect them into a map, or write them to a directory.
+//!
+//! ```
+//! let md = "# Hello\n\n```python\n# hello.py\nprint('hi')\n```\n";
+//!
+//! let files = glu::Options::new().to_map(md).unwrap();
+//! assert_eq!(files["hello.py"], "print('hi')\n");
+//! ```
+//!
+//! A `build.rs` can use [`Options::write_to`] to extract examples from docs
+//! into `OUT_DIR`. The lower-level pieces ([`parse()`], [`Segment`],
+//! [`FileType`], [`write_files`]) are exported too, for anything else.
+
+#![warn(missing_docs)]
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+mod archive;
+mod config;
+mod doctest;
+mod download;
+mod export;
+mod filetype;
+mod history;
+mod inputs;
+mod merge;
+mod meta;
+mod naming;
+mod pack;
+mod plan;
+mod project;
+mod segment;
+mod segment_optimizer;
+mod parse;
+mod sniff;
+mod steps;
+mod sync;
+/// Turning the code in docs into `cargo test` tests, from a `build.rs`
+pub mod testgen;
+mod watch;
+mod writer;
+
+// The glu command itself, which needs all of the above. It's only public so
+// src/main.rs can get at it.
+#[doc(hidden)]
+pub mod cli;
+
+pub use crate::config::{Config, ConfigError, LanguageDefaults};
+pub use crate::filetype::FileType;
+pub use crate::merge::{Conflict, MergeError};
+pub use crate::meta::Meta;
+pub use crate::parse::{Document, parse, parse_document};
+pub use crate::segment::{Detection, Kind, Section, Segment, Source};
+pub use crate::sniff::{Clue, Confidence, Guess};
+pub use crate::writer::{to_map, write_files};
+
+// ----------------------------------------------------------------------------
+// The simple way in
+// ----------------------------------------------------------------------------
+
+/// Anything that can go wrong turning a document into files.
+#[derive(Debug)]
+pub enum Error {
+    /// The front matter's `glu` settings didn't make sense
+    Config(ConfigError),
+    /// Several blocks wrote to one file, and that was declared an error
+    Merge(MergeError),
+    /// Writing the files failed
+    Io(io::Error),
+    /// Extra languages couldn't be read, or came too late
+    Languages(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(e) => write!(f, "{}", e),
+            Error::Merge(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Languages(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Config(e) => Some(e),
+            Error::Merge(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Languages(_) => None,
+        }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Error { Error::Config(e) }
+}
+
+impl From<MergeError> for Error {
+    fn from(e: MergeError) -> Error { Error::Merge(e) }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error { Error::Io(e) }
+}
+
+/// Know about more languages than the built-in ones, from TOML in the same
+/// format as glu's own list (see the README). Only glu the command reads the
+/// user's `languages.toml`; library callers that want it pass it in here. It
+/// applies to the whole process, so it has to come before anything else.
+pub fn add_languages(toml_text: &str) -> Result<(), Error> {
+    let languages = filetype::parse_languages(toml_text).map_err(|e| Error::Languages(e.to_string()))?;
+    filetype::add_languages(languages).map_err(Error::Languages)
+}
+
+/// How to turn a document into files. The defaults match running `glu` with
+/// no flags; the document's own front matter is honoured either way.
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Options {
+    conflict: Option<Conflict>,
+    name: Option<String>,
+}
+
+impl Options {
+    /// The same as running `glu` with no flags
+    pub fn new() -> Options {
+        Options::default()
+    }
+
+    /// What to do when several blocks write to the same file, overriding the
+    /// document's front matter. Blocks can still pick their own.
+    pub fn conflict(mut self, conflict: Conflict) -> Options {
+        self.conflict = Some(conflict);
+        self
+    }
+
+    /// What to call the document in error messages and [`Source`]s, usually
+    /// its path.
+    pub fn name(mut self, name: &str) -> Options {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// The files a document describes, merged and with its front matter
+    /// applied, in the order they first appear.
+    pub fn files(&self, md_text: &str) -> Result<Vec<Segment>, Error> {
+        let mut doc = parse_document(md_text)?;
+        if let Some(name) = &self.name {
+            doc.set_name(name);
+        }
+        let (segments, _) = project::files([&doc], self.conflict)?;
+        Ok(segments)
+    }
+
+    /// File name -> contents, for everything the document describes.
+    pub fn to_map(&self, md_text: &str) -> Result<BTreeMap<String, String>, Error> {
+        Ok(to_map(&self.files(md_text)?))
+    }
+
+    /// Write the document's files under dir, creating it if needed, and
+    /// return what was written. Paths can't escape dir.
+    pub fn write_to<P: AsRef<Path>>(&self, md_text: &str, dir: P) -> Result<Vec<Segment>, Error> {
+        let segments = self.files(md_text)?;
+        write_files(dir, &segments)?;
+        Ok(segments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const DOC: &str = "---\nglu:\n  skip: [skipped.txt]\n---\n\n\
+        ```bash\n# run.sh\necho one\n```\n\n\
+        ```bash\n# run.sh\necho two\n```\n\n\
+        ```text title=skipped.txt\nnope\n```\n";
+
+    #[test]
+    fn files() {
+        let files = Options::new().files(DOC).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_name, "run.sh");
+        assert_eq!(files[0].contents, "echo two\n");
+
+        let map = Options::new().conflict(Conflict::Append).to_map(DOC).unwrap();
+        assert_eq!(map["run.sh"], "echo one\necho two\n");
+    }
+
+    #[test]
+    fn errors() {
+        let err = Options::new().name("doc.md").conflict(Conflict::Error).files(DOC).unwrap_err();
+        assert!(matches!(err, Error::Merge(_)));
+        assert!(err.to_string().contains("doc.md"), "{}", err);
+
+        let err = Options::new().files("---\nglu: [\n---\n").unwrap_err();
+        assert!(matches!(err, Error::Config(_)));
+    }
+
+    #[test]
+    fn write_to() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let written = Options::new().write_to(DOC, tmp.path().join("out")).unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(std::fs::read_to_string(tmp.path().join("out/run.sh")).unwrap(), "echo two\n");
+    }
+}
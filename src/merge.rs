@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::segment::{Segment, Source};
+
+// ----------------------------------------------------------------------------
+// What happens when several blocks resolve to the same file
+// ----------------------------------------------------------------------------
+
+/// What to do when several blocks are for the same file
+#[derive(PartialEq, Debug, Copy, Clone, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Conflict {
+    /// Each block replaces whatever came before it
+    #[default]
+    LastWins,
+    /// Blocks are concatenated in document order
+    Append,
+    /// Two blocks for one file is a mistake in the document
+    Error,
+}
+
+impl Conflict {
+    /// The policy a `conflict=...` in a block's fence meta names, if any
+    pub fn from_meta(value: &str) -> Option<Conflict> {
+        Conflict::from_str(value, true).ok()
+    }
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Outcome {
+    Single,
+    Appended,
+    Overwritten,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct FileReport {
+    pub file_name: String,
+    pub blocks: usize,
+    pub outcome: Outcome,
+    // Where each of those blocks came from, as far as we know
+    pub sources: Vec<Source>,
+}
+
+fn locations(sources: &[Source]) -> String {
+    sources.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl fmt::Display for FileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.outcome {
+            Outcome::Single => write!(f, "{}: 1 block", self.file_name)?,
+            Outcome::Appended => write!(f, "{}: {} blocks appended", self.file_name, self.blocks)?,
+            Outcome::Overwritten => write!(f, "{}: {} blocks, last one wins", self.file_name, self.blocks)?,
+        }
+        match self.blocks > 1 && !self.sources.is_empty() {
+            true => write!(f, " ({})", locations(&self.sources)),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Several blocks wrote to one file, under [`Conflict::Error`]
+#[derive(PartialEq, Debug)]
+pub struct MergeError {
+    /// The file they all wrote to
+    pub file_name: String,
+    /// Where each of those blocks came from, as far as we know
+    pub sources: Vec<Source>,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is written by more than one block", self.file_name)?;
+        match self.sources.is_empty() {
+            true => Ok(()),
+            false => write!(f, " ({})", locations(&self.sources)),
+        }
+    }
+}
+
+impl Error for MergeError {}
+
+// Combine segments that share a file name. Each block's own policy decides how
+// it lands on top of the earlier ones, falling back to the document's policy.
+// Files come out in the order they first appear.
+pub fn merge(segments: Vec<Segment>, default: Conflict) -> Result<(Vec<Segment>, Vec<FileReport>), MergeError> {
+    let mut merged: Vec<Segment> = vec![];
+    let mut reports: Vec<FileReport> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for segment in segments {
+        let Some(&i) = index.get(&segment.file_name) else {
+            index.insert(segment.file_name.clone(), merged.len());
+            reports.push(FileReport {
+                file_name: segment.file_name.clone(),
+                blocks: 1,
+                outcome: Outcome::Single,
+                sources: segment.source.iter().cloned().collect(),
+            });
+            merged.push(segment);
+            continue;
+        };
+
+        let report = &mut reports[i];
+        report.blocks += 1;
+        report.sources.extend(segment.source.iter().cloned());
+        match segment.conflict.unwrap_or(default) {
+            Conflict::Error => return Err(MergeError {
+                file_name: segment.file_name,
+                sources: report.sources.clone(),
+            }),
+            Conflict::Append => {
+                merged[i].contents.push_str(&segment.contents);
+                merged[i].mode = merged[i].mode.or(segment.mode);
+                if report.outcome == Outcome::Single {
+                    report.outcome = Outcome::Appended;
+                }
+            },
+            Conflict::LastWins => {
+                merged[i] = segment;
+                report.outcome = Outcome::Overwritten;
+            },
+        }
+    }
+    Ok((merged, reports))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::Kind;
+
+    fn seg(name: &str, contents: &str, conflict: Option<Conflict>) -> Segment {
+        Segment {
+            kind: Kind::File,
+            file_name: name.into(),
+            detection: Default::default(),
+            file_type: "text".into(),
+            contents: contents.into(),
+            conflict: conflict,
+            mode: None,
+            meta: Default::default(),
+            source: None,
+            section: None,
+            guess: None,
+        }
+    }
+
+    fn names(segments: &[Segment]) -> Vec<&str> {
+        segments.iter().map(|s| s.file_name.as_str()).collect()
+    }
+
+    #[test]
+    fn no_conflicts() {
+        let (merged, reports) = merge(vec![
+            seg("a.txt", "a\n", None),
+            seg("b.txt", "b\n", None),
+        ], Conflict::Error).unwrap();
+        assert_eq!(names(&merged), vec!["a.txt", "b.txt"]);
+        assert!(reports.iter().all(|r| r.outcome == Outcome::Single));
+    }
+
+    #[test]
+    fn last_wins() {
+        let (merged, reports) = merge(vec![
+            seg("a.txt", "first\n", None),
+            seg("b.txt", "b\n", None),
+            seg("a.txt", "second\n", None),
+        ], Conflict::LastWins).unwrap();
+        assert_eq!(names(&merged), vec!["a.txt", "b.txt"]);
+        assert_eq!(merged[0].contents, "second\n");
+        assert_eq!(reports[0], FileReport {
+            file_name: "a.txt".into(),
+            blocks: 2,
+            outcome: Outcome::Overwritten,
+            sources: vec![],
+        });
+    }
+
+    #[test]
+    fn append() {
+        let (merged, reports) = merge(vec![
+            seg("a.txt", "one\n", None),
+            seg("a.txt", "two\n", None),
+            seg("a.txt", "three\n", None),
+        ], Conflict::Append).unwrap();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].contents, "one\ntwo\nthree\n");
+        assert_eq!(reports[0].to_string(), "a.txt: 3 blocks appended");
+    }
+
+    #[test]
+    fn error() {
+        let result = merge(vec![
+            seg("a.txt", "one\n", None),
+            seg("a.txt", "two\n", None),
+        ], Conflict::Error);
+        assert_eq!(result, Err(MergeError { file_name: "a.txt".into(), sources: vec![] }));
+    }
+
+    #[test]
+    fn per_block_policy() {
+        // The document says error, but the block opts into appending
+        let (merged, _) = merge(vec![
+            seg("a.txt", "one\n", None),
+            seg("a.txt", "two\n", Some(Conflict::Append)),
+        ], Conflict::Error).unwrap();
+        assert_eq!(merged[0].contents, "one\ntwo\n");
+
+        // And the other way around
+        let result = merge(vec![
+            seg("a.txt", "one\n", None),
+            seg("a.txt", "two\n", Some(Conflict::Error)),
+        ], Conflict::Append);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn from_meta() {
+        assert_eq!(Conflict::from_meta("append"), Some(Conflict::Append));
+        assert_eq!(Conflict::from_meta("last-wins"), Some(Conflict::LastWins));
+        assert_eq!(Conflict::from_meta("error"), Some(Conflict::Error));
+        assert_eq!(Conflict::from_meta("bogus"), None);
+    }
+
+    #[test]
+    fn explained_by_source() {
+        let from = |doc: &str, line: usize| Some(Source {
+            document: Some(doc.into()),
+            start_line: line,
+            ..Default::default()
+        });
+        let mut first = seg("a.txt", "one\n", None);
+        first.source = from("docs/01.md", 3);
+        let mut second = seg("a.txt", "two\n", None);
+        second.source = from("docs/02.md", 10);
+
+        let (_, reports) = merge(vec![first.clone(), second.clone()], Conflict::LastWins).unwrap();
+        assert_eq!(reports[0].to_string(), "a.txt: 2 blocks, last one wins (docs/01.md:3, docs/02.md:10)");
+
+        let err = merge(vec![first, second], Conflict::Error).unwrap_err();
+        assert_eq!(err.to_string(), "a.txt is written by more than one block (docs/01.md:3, docs/02.md:10)");
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+// ----------------------------------------------------------------------------
+// Fence meta: everything after the language in the info string
+// ----------------------------------------------------------------------------
+//
+// ```rust title="src/lib.rs" ignore
+//
+// Different static site generators spell this differently - Docusaurus likes
+// `title="..."`, Hugo wraps things in `{...}`, and plenty of people just write
+// a bare path. We try to accept all of those without being too clever.
+
+/// A fence's info string after the language, split into `key=value`
+/// attributes and bare flags
+#[derive(PartialEq, Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Meta {
+    /// `title="src/lib.rs"`, quotes removed
+    pub attrs: BTreeMap<String, String>,
+    /// Everything else, like `ignore` or a bare path, in order
+    pub flags: Vec<String>,
+}
+
+// Keys that name the file a block should be written to, in priority order.
+const PATH_KEYS: [&str; 4] = ["title", "file", "filename", "path"];
+
+impl From<&str> for Meta {
+    fn from(item: &str) -> Meta {
+        let mut meta = Meta::default();
+        for token in tokenize(item) {
+            match token.split_once('=') {
+                Some((k, v)) => { meta.attrs.insert(k.to_string(), unquote(v)); },
+                None => meta.flags.push(unquote(&token)),
+            }
+        }
+        meta
+    }
+}
+
+impl From<&Option<String>> for Meta {
+    fn from(item: &Option<String>) -> Meta {
+        match &item {
+            Some(s) => Meta::from(&s[..]),
+            None => Meta::default(),
+        }
+    }
+}
+
+impl Meta {
+    /// The value of an attribute
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).map(|v| v.as_str())
+    }
+
+    /// Whether a bare word is there
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    /// An explicit path, if the author gave one. Named attributes win over a
+    /// bare path-looking word.
+    pub fn path(&self) -> Option<String> {
+        PATH_KEYS
+            .iter()
+            .find_map(|k| self.get(k))
+            .or_else(|| self.flags.iter().map(|f| f.as_str()).find(|f| is_path_like(f)))
+            .filter(|p| !p.is_empty())
+            .map(|p| p.to_string())
+    }
+}
+
+fn is_path_like(word: &str) -> bool {
+    (word.contains('.') || word.contains('/'))
+        && word.chars().next().is_some_and(|ch| ch.is_alphanumeric() || "._/".contains(ch))
+}
+
+// Split on whitespace, commas and braces, except inside quotes.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+
+    for ch in text.chars() {
+        match quote {
+            Some(q) => {
+                current.push(ch);
+                if ch == q {
+                    quote = None;
+                }
+            },
+            None if ch == '"' || ch == '\'' => {
+                current.push(ch);
+                quote = Some(ch);
+            },
+            None if ch.is_whitespace() || ",{}".contains(ch) => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            },
+            None => current.push(ch),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn unquote(text: &str) -> String {
+    for q in ['"', '\''] {
+        if text.len() >= 2 && text.starts_with(q) && text.ends_with(q) {
+            return text[1..text.len()-1].to_string();
+        }
+    }
+    text.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attrs(pairs: Vec<(&str, &str)>) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!(Meta::from(""), Meta::default());
+        assert_eq!(Meta::from(r#"title="src/lib.rs" ignore"#), Meta {
+            attrs: attrs(vec![("title", "src/lib.rs")]),
+            flags: vec!["ignore".to_string()],
+        });
+        assert_eq!(Meta::from("{linenos=table, hl_lines='1 3'}"), Meta {
+            attrs: attrs(vec![("linenos", "table"), ("hl_lines", "1 3")]),
+            flags: vec![],
+        });
+    }
+
+    #[test]
+    fn from_option_string() {
+        assert_eq!(Meta::from(&None), Meta::default());
+        assert_eq!(Meta::from(&Some("run".to_string())).flags, vec!["run".to_string()]);
+    }
+
+    #[test]
+    fn path() {
+        assert_eq!(Meta::from("").path(), None);
+        assert_eq!(Meta::from("ignore").path(), None);
+        assert_eq!(Meta::from(r#"title="src/lib.rs""#).path(), Some("src/lib.rs".to_string()));
+        assert_eq!(Meta::from("file=main.c").path(), Some("main.c".to_string()));
+        assert_eq!(Meta::from("src/main.rs").path(), Some("src/main.rs".to_string()));
+        assert_eq!(Meta::from(".gitignore").path(), Some(".gitignore".to_string()));
+        assert_eq!(Meta::from("Makefile").path(), None);
+        assert_eq!(Meta::from(r#"title="" other.rs"#).path(), None);
+
+        // Attributes beat bare words, regardless of order
+        assert_eq!(Meta::from("bare.rs file=named.rs").path(), Some("named.rs".to_string()));
+        assert_eq!(Meta::from("file=named.rs title=titled.rs").path(), Some("titled.rs".to_string()));
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeSet;
+use crate::filetype::FileType;
+use crate::segment::{Detection, Segment};
+
+// ----------------------------------------------------------------------------
+// Names for blocks that don't say where they go
+// ----------------------------------------------------------------------------
+//
+// Rather than all landing in one file and clobbering each other, each one
+// gets a name of its own from a template:
+//
+//     {heading}-{index}.{ext}   ->   install-the-tools-03.sh
+//
+// {index} is the block's number among all the files being unpacked together,
+// two digits, counting on from one document to the next, {heading} is a slug
+// of its section heading ("snippet" before the first), {ext} is its
+// language's usual extension ("txt" if glu doesn't know it) and {lang} is
+// the language's name. The same documents always get the same names,
+// whatever's filtered out afterwards, and no name is handed out twice.
+
+pub const DEFAULT_TEMPLATE: &str = "{heading}-{index}.{ext}";
+
+const PLACEHOLDERS: [&str; 4] = ["index", "heading", "ext", "lang"];
+
+/// An error if the template uses a placeholder there's no value for.
+pub fn check_template(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            return Err(format!("unclosed {{ in {:?}", template));
+        };
+        let name = &rest[start + 1..start + len];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!("unknown placeholder {{{}}} in {:?}, expected one of {}", name, template, PLACEHOLDERS.join(", ")));
+        }
+        rest = &rest[start + len + 1..];
+    }
+    Ok(())
+}
+
+// `Step 2: Add *tests*` -> `step-2-add-tests`
+fn slug(title: &str) -> String {
+    let mut slug = String::new();
+    for ch in title.chars() {
+        match ch.is_ascii_alphanumeric() {
+            true => slug.push(ch.to_ascii_lowercase()),
+            false => if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            },
+        }
+        if slug.len() >= 40 {
+            break;
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+fn render(template: &str, index: usize, segment: &Segment) -> String {
+    let heading = segment.section.as_ref().map(|s| slug(&s.title)).filter(|s| !s.is_empty());
+    let ft = FileType::from(&segment.file_type[..]);
+    template
+        .replace("{index}", &format!("{:02}", index))
+        .replace("{heading}", heading.as_deref().unwrap_or("snippet"))
+        .replace("{ext}", ft.extensions().first().copied().unwrap_or("txt"))
+        .replace("{lang}", &segment.file_type)
+}
+
+// `a/b.py` -> `a/b-2.py`, for when a template doesn't make a name unique
+fn numbered(name: &str, n: usize) -> String {
+    let stem_start = name.rfind('/').map_or(0, |i| i + 1);
+    match name[stem_start..].rfind('.') {
+        Some(dot) if dot > 0 => format!("{}-{}{}", &name[..stem_start + dot], n, &name[stem_start + dot..]),
+        _ => format!("{}-{}", name, n),
+    }
+}
+
+/// Hands out names across a set of documents, so two documents' unnamed
+/// blocks never land on the same file.
+pub struct Namer {
+    taken: BTreeSet<String>,
+    index: usize,
+}
+
+impl Namer {
+    /// Start from every block in every document, so the names they give
+    /// themselves are never handed out.
+    pub fn new<'a, I>(segments: I) -> Namer
+    where
+        I: IntoIterator<Item = &'a Segment>,
+    {
+        let taken = segments
+            .into_iter()
+            .filter(|s| s.detection != Detection::Fallback)
+            .map(|s| s.file_name.clone())
+            .collect();
+        Namer { taken: taken, index: 0 }
+    }
+
+    /// Give every block without a path its own name, from the template.
+    /// Blocks should be one document's files, in document order, with
+    /// documents in the order they're unpacked.
+    pub fn name(&mut self, template: &str, mut segments: Vec<Segment>) -> Vec<Segment> {
+        for segment in segments.iter_mut() {
+            self.index += 1;
+            if segment.detection != Detection::Fallback {
+                continue;
+            }
+            let name = render(template, self.index, segment);
+            let mut candidate = name.clone();
+            let mut n = 2;
+            while !self.taken.insert(candidate.clone()) {
+                candidate = numbered(&name, n);
+                n += 1;
+            }
+            segment.file_name = candidate;
+        }
+        segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+
+    const DOC: &str = "```python\nprint(1)\n```\n\n\
+        ## Install the *tools*\n\n```bash\necho hi\n```\n\n```\nplain\n```\n\n\
+        ## Step 2\n\n```python\n# app.py\npass\n```\n\n```python\npass\n```\n";
+
+    fn names(template: &str) -> Vec<String> {
+        let segments = parse(DOC);
+        Namer::new(&segments).name(template, segments).into_iter().map(|s| s.file_name).collect()
+    }
+
+    #[test]
+    fn default_names() {
+        assert_eq!(names(DEFAULT_TEMPLATE), vec![
+            "snippet-01.py",
+            "install-the-tools-02.sh",
+            "install-the-tools-03.txt",
+            "app.py",
+            "step-2-05.py",
+        ]);
+    }
+
+    #[test]
+    fn templates() {
+        assert_eq!(names("snippets/{lang}/{index}.{ext}")[..3], [
+            "snippets/python/01.py",
+            "snippets/bash/02.sh",
+            "snippets/unknown/03.txt",
+        ]);
+
+        // Still unique, even when the template doesn't make them so
+        assert_eq!(names("{heading}.{ext}"), vec![
+            "snippet.py",
+            "install-the-tools.sh",
+            "install-the-tools.txt",
+            "app.py",
+            "step-2.py",
+        ]);
+        assert_eq!(names("app.py"), vec!["app-2.py", "app-3.py", "app-4.py", "app.py", "app-5.py"]);
+    }
+
+    #[test]
+    fn across_documents() {
+        let mut namer = Namer::new(parse(DOC).iter().chain(parse("```python\n# snippet-06.py\n```\n").iter()));
+        let first: Vec<String> = namer.name(DEFAULT_TEMPLATE, parse(DOC)).into_iter().map(|s| s.file_name).collect();
+        let second: Vec<String> = namer.name(DEFAULT_TEMPLATE, parse("```python\npass\n```\n\n```python\npass\n```\n"))
+            .into_iter().map(|s| s.file_name).collect();
+        assert_eq!(first[0], "snippet-01.py");
+        assert_eq!(second, vec!["snippet-06-2.py", "snippet-07.py"]);
+    }
+
+    #[test]
+    fn checking() {
+        assert_eq!(check_template(DEFAULT_TEMPLATE), Ok(()));
+        assert_eq!(check_template("no-placeholders.txt"), Ok(()));
+        assert!(check_template("{name}.txt").is_err());
+        assert!(check_template("{index.txt").is_err());
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!(slug("Step 2: Add *tests*!"), "step-2-add-tests");
+        assert_eq!(slug("???"), "");
+        assert_eq!(numbered("dir.d/file", 2), "dir.d/file-2");
+        assert_eq!(numbered("a/.env", 3), "a/.env-3");
+        assert_eq!(numbered("b.tar.gz", 2), "b.tar-2.gz");
+    }
+}
@@ -0,0 +1,258 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::filetype::FileType;
+use crate::parse::parse;
+
+// ----------------------------------------------------------------------------
+// Directory -> Markdown, the reverse of write_files
+// ----------------------------------------------------------------------------
+
+// Every file under root, sorted, as paths relative to root. Version control
+// directories aren't part of the project. Symlinks to files are packed as the
+// file, but symlinks to directories are skipped: one pointing back up the
+// tree would never end.
+fn walk(root: &Path, dir: &Path, output_buf: &mut Vec<PathBuf>, warnings: &mut Vec<String>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+
+    for path in entries {
+        let rel = path.strip_prefix(root).unwrap().to_path_buf();
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_dir() {
+            if path.file_name().is_some_and(|n| n == ".git") {
+                continue;
+            }
+            walk(root, &path, output_buf, warnings)?;
+        } else if file_type.is_symlink() && path.is_dir() {
+            warnings.push(format!("skipping {}, it's a symlink to a directory", rel.display()));
+        } else {
+            output_buf.push(rel);
+        }
+    }
+    Ok(())
+}
+
+// Long enough that nothing inside the block can close it early
+fn fence_for(contents: &str) -> String {
+    let longest = contents
+        .lines()
+        .map(|l| l.trim_start().chars().take_while(|ch| *ch == '`').count())
+        .max()
+        .unwrap_or_default();
+    "`".repeat(longest.max(2) + 1)
+}
+
+#[cfg(unix)]
+fn file_mode(path: &Path) -> io::Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(Some(fs::metadata(path)?.permissions().mode() & 0o777))
+}
+
+#[cfg(not(unix))]
+fn file_mode(_path: &Path) -> io::Result<Option<u32>> {
+    Ok(None)
+}
+
+// Whether glu would unpack this block back to exactly this file, mode and
+// all if we know it
+fn roundtrips(block: &str, path: &str, contents: &str, mode: Option<u32>) -> bool {
+    let segments = parse(block);
+    segments.len() == 1
+        && segments[0].file_name == path
+        && segments[0].contents == contents
+        && (mode.is_none() || segments[0].mode == packed_mode(contents, mode))
+}
+
+// The mode a block has to spell out: executables', since only a shebang makes
+// a file executable otherwise, and shebang files' that aren't
+fn packed_mode(contents: &str, mode: Option<u32>) -> Option<u32> {
+    mode.filter(|m| m & 0o111 != 0 || contents.starts_with("#!"))
+}
+
+// One fenced block for one file, with its mode if it's known and worth
+// keeping. The preferred form is a path comment, same as a person would
+// write. Files that glu's tidying would change (indentation, blank lines at
+// either end) go in verbatim instead, with the path in the meta.
+pub fn pack_file(path: &str, contents: &str, mode: Option<u32>) -> String {
+    let meta = packed_mode(contents, mode).map(|m| format!(" mode={:o}", m)).unwrap_or_default();
+    let ft = FileType::from_path(path);
+    // The meta can only follow a language, so unknown types still need one
+    let lang = match ft == FileType::UNKNOWN {
+        true => "text".to_string(),
+        false => String::from(ft),
+    };
+    let fence = fence_for(contents);
+
+    if let Some(comment) = ft.path_comment(path) {
+        // A shebang has to stay on the first line, so the comment goes under it
+        let body = match contents.split_once('\n') {
+            Some((shebang, rest)) if shebang.starts_with("#!") => format!("{}\n{}\n{}", shebang, comment, rest),
+            _ => format!("{}\n\n{}", comment, contents),
+        };
+        let block = format!("{}{}{}\n{}{}\n", fence, lang, meta, body, fence);
+        if roundtrips(&block, path, contents, mode) {
+            return block;
+        }
+    }
+
+    let body = contents.strip_suffix('\n').unwrap_or(contents);
+    format!("{}{} title=\"{}\" verbatim{}\n{}\n{}\n", fence, lang, path, meta, body, fence)
+}
+
+// The Markdown for a whole directory, and warnings about files that won't come
+// back out exactly as they went in, or at all
+pub fn pack<R: AsRef<Path>>(root: R) -> io::Result<(String, Vec<String>)> {
+    let root = root.as_ref();
+    let mut paths: Vec<PathBuf> = vec![];
+    let mut warnings: Vec<String> = vec![];
+    walk(root, root, &mut paths, &mut warnings)?;
+
+    let mut blocks: Vec<String> = vec![];
+    for rel in paths {
+        let name = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        match fs::read_to_string(root.join(&rel)) {
+            Ok(contents) => {
+                let mode = file_mode(&root.join(&rel))?;
+                let block = pack_file(&name, &contents, mode);
+                if !roundtrips(&block, &name, &contents, mode) {
+                    warnings.push(format!("{} will not unpack byte-for-byte (missing final newline?)", name));
+                }
+                blocks.push(block);
+            },
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                warnings.push(format!("skipping {}, it isn't UTF-8 text", name));
+            },
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((blocks.join("\n"), warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+    use crate::segment::Segment;
+    use crate::writer::write_files;
+
+    #[test]
+    fn test_fence_for() {
+        assert_eq!(fence_for(""), "```");
+        assert_eq!(fence_for("a `b` c"), "```");
+        assert_eq!(fence_for("```rust\n```\n"), "````");
+        assert_eq!(fence_for("  `````\n"), "``````");
+    }
+
+    #[test]
+    fn comment_form() {
+        assert_eq!(pack_file("src/main.rs", "fn main() {}\n", None),
+            "```rust\n// src/main.rs\n\nfn main() {}\n```\n");
+        assert_eq!(pack_file("style.css", "body {}\n", Some(0o644)),
+            "```css\n/* style.css */\n\nbody {}\n```\n");
+        assert_eq!(pack_file("main.sh", "#!/bin/bash\n\nnode fib.js\n", None),
+            "```bash\n#!/bin/bash\n# main.sh\n\nnode fib.js\n```\n");
+    }
+
+    #[test]
+    fn verbatim_form() {
+        // Indentation would get trimmed
+        assert_eq!(pack_file("x.py", "    pass\n", None),
+            "```python title=\"x.py\" verbatim\n    pass\n```\n");
+
+        // Unknown types have no comment syntax
+        assert_eq!(pack_file("notes.txt", "hi\n", None),
+            "```text title=\"notes.txt\" verbatim\nhi\n```\n");
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!(pack_file("main.sh", "#!/bin/bash\necho hi\n", Some(0o700)),
+            "```bash mode=700\n#!/bin/bash\n# main.sh\necho hi\n```\n");
+        assert_eq!(pack_file("x.py", "    pass\n", Some(0o755)),
+            "```python title=\"x.py\" verbatim mode=755\n    pass\n```\n");
+        assert_eq!(pack_file("main.rs", "fn main() {}\n", Some(0o644)),
+            "```rust\n// main.rs\n\nfn main() {}\n```\n");
+    }
+
+    #[test]
+    fn roundtrip() {
+        let files = [
+            ("Makefile", "all:\n\tcc main.c\n", 0o644),
+            ("README.txt", "\nStarts with a blank line.\n\n", 0o644),
+            ("bin/tool", "echo no shebang\n", 0o755),
+            ("docs/example.md", "```js\n// nested.js\n```\n", 0o644),
+            ("main.c", "#include <stdio.h>\nint main() { return 0; }\n", 0o644),
+            ("scripts/run.sh", "#!/bin/sh\necho hi\n", 0o750),
+            ("scripts/source-me.sh", "#!/bin/sh\nexport X=1\n", 0o644),
+            ("src/lib.rs", "    // indented\nfn x() {}\n", 0o644),
+        ];
+        let segments: Vec<Segment> = files.iter().map(|(name, contents, mode)| Segment {
+            file_name: name.to_string(),
+            contents: contents.to_string(),
+            mode: Some(*mode),
+            ..Default::default()
+        }).collect();
+
+        let tmp = TempDir::new("glu-test").unwrap();
+        let packed = tmp.path().join("packed");
+        write_files(&packed, &segments).unwrap();
+        let (md, warnings) = pack(&packed).unwrap();
+        assert_eq!(warnings, Vec::<String>::new());
+
+        let segments = parse(&md);
+        let unpacked: Vec<(String, String)> = segments
+            .iter()
+            .map(|s| (s.file_name.clone(), s.contents.clone()))
+            .collect();
+        let expected: Vec<(String, String)> = files
+            .iter()
+            .map(|(n, c, _)| (n.to_string(), c.to_string()))
+            .collect();
+        assert_eq!(unpacked, expected);
+
+        #[cfg(unix)]
+        {
+            let again = tmp.path().join("unpacked");
+            write_files(&again, &segments).unwrap();
+            for (name, _, mode) in files {
+                assert_eq!(file_mode(&again.join(name)).unwrap(), Some(mode), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn warnings() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        std::fs::write(tmp.path().join("binary.dat"), [0xff, 0xfe, 0x00]).unwrap();
+        std::fs::write(tmp.path().join("no-newline.txt"), "hi").unwrap();
+        let (md, warnings) = pack(tmp.path()).unwrap();
+        assert_eq!(warnings, vec![
+            "skipping binary.dat, it isn't UTF-8 text",
+            "no-newline.txt will not unpack byte-for-byte (missing final newline?)",
+        ]);
+        assert!(md.contains("no-newline.txt"), "{}", md);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let root = tmp.path();
+        std::fs::create_dir(root.join("src")).unwrap();
+        std::fs::write(root.join("src/main.py"), "pass\n").unwrap();
+        std::os::unix::fs::symlink(root, root.join("src/loop")).unwrap();
+        std::os::unix::fs::symlink(root.join("src/main.py"), root.join("link.py")).unwrap();
+
+        let (md, warnings) = pack(root).unwrap();
+        let names: Vec<String> = parse(&md).into_iter().map(|s| s.file_name).collect();
+        assert_eq!(names, vec!["link.py", "src/main.py"]);
+        assert_eq!(warnings, vec!["skipping src/loop, it's a symlink to a directory"]);
+    }
+}
@@ -1,31 +1,195 @@
-use markdown::{to_mdast, ParseOptions};
+use markdown::{to_mdast, Constructs, ParseOptions};
 use markdown::mdast::{Node,Code};
-use crate::segment::Segment;
+use crate::config::{Config, ConfigError};
+use crate::segment::{Kind, Section, Segment};
 
-// This wraps up the higher-level process of generating segments from MD text.
+/// A whole document: its glu settings, and what it unpacks to
+#[derive(PartialEq, Debug)]
+pub struct Document {
+    /// The settings from its front matter
+    pub config: Config,
+    /// Every code block, in document order
+    pub segments: Vec<Segment>,
+    /// Every section heading, including ones with no code under them
+    pub sections: Vec<Section>,
+}
+
+impl Document {
+    /// Copies of the blocks of one kind, in document order
+    pub fn of_kind(&self, kind: Kind) -> Vec<Segment> {
+        self.segments.iter().filter(|s| s.kind == kind).cloned().collect()
+    }
+
+    /// Record which document every segment came from
+    pub fn set_name(&mut self, name: &str) {
+        for source in self.segments.iter_mut().filter_map(|s| s.source.as_mut()) {
+            source.document = Some(name.to_string());
+        }
+    }
+
+    /// The first section whose heading starts with `title`, ignoring case
+    pub fn find_section(&self, title: &str) -> Option<&Section> {
+        let title = title.trim().to_lowercase();
+        self.sections.iter().find(|s| s.title.to_lowercase().starts_with(&title))
+    }
+
+    /// Drop every block after the given section, so the document stops
+    /// where that section ends. Section 0 is everything before the first.
+    pub fn truncate_after(&mut self, number: usize) {
+        self.segments.retain(|s| s.section.as_ref().map_or(0, |s| s.number) <= number);
+        self.sections.retain(|s| s.number <= number);
+    }
+}
+
+/// Parse a document, front matter and all. Blocks aren't merged or filtered
+/// yet; see [`crate::Options`] for that.
+pub fn parse_document(md_text: &str) -> Result<Document, ConfigError> {
+    let (segments, sections) = parse_sections(md_text);
+    Ok(Document {
+        config: Config::from_markdown(md_text)?,
+        segments: segments,
+        sections: sections,
+    })
+}
+
+/// Every code block in the Markdown text, in document order, ignoring front
+/// matter.
 //
 // There's probably a lot of room to improve performance by eliminating copy
 // operations in memory - the sloppiness is because I'm still learning Rust.
 // I promise it itches me, but this will probably always be adequate.
 // Realistically the bottleneck will be file IO.
 pub fn parse(md_text: &str) -> Vec<Segment> {
+    return parse_sections(md_text).0;
+}
+
+// Code blocks, and the section headings they sit under
+fn parse_sections(md_text: &str) -> (Vec<Segment>, Vec<Section>) {
+    // Front matter is understood, so it doesn't get mistaken for content
+    let options = ParseOptions {
+        constructs: Constructs {
+            frontmatter: true,
+            ..Constructs::default()
+        },
+        ..ParseOptions::default()
+    };
+
     // Per docs: cannot fail with MDX off
-    let tree = to_mdast(md_text, &ParseOptions::default()).unwrap();
+    let tree = to_mdast(md_text, &options).unwrap();
 
-    // Build a buffer of Code structs
-    let mut code_nodes: Vec<Code> = vec![];
-    find_code_nodes(&tree, &mut code_nodes);
+    // Build a buffer of code blocks and the headings around them
+    let mut nodes: Vec<Found> = vec![];
+    find_nodes(&tree, &mut nodes);
 
-    // Convert them all!
-    return code_nodes.iter().map(|c| Segment::from(c)).collect();
+    // Convert them all, noting which section each one is in
+    let level = section_level(&nodes);
+    let mut section: Option<Section> = None;
+    let mut sections: Vec<Section> = vec![];
+    let mut segments: Vec<Segment> = vec![];
+    for node in nodes {
+        match node {
+            Found::Heading(depth, title) if Some(depth) == level => {
+                section = Some(Section { number: sections.len() + 1, title: title });
+                sections.extend(section.clone());
+            },
+            Found::Heading(..) => {},
+            Found::Code(code) => segments.push(Segment {
+                section: section.clone(),
+                ..Segment::from(code)
+            }),
+        }
+    }
+    return (segments, sections);
 }
 
-// Recursively explore a Markdown parse tree and find all the code segments
-fn find_code_nodes(node: &Node, output_buf: &mut Vec<Code>) {
+// What we care about in a parse tree
+enum Found<'a> {
+    Heading(u8, String),
+    Code(&'a Code),
+}
+
+// Recursively explore a Markdown parse tree and find all the code segments,
+// and the headings between them
+fn find_nodes<'a>(node: &'a Node, output_buf: &mut Vec<Found<'a>>) {
     match node {
-        Node::Code(c) => output_buf.push(c.clone()),
-        _ => node.children().unwrap_or(&vec![]).iter().for_each(
-            |n| find_code_nodes(n, output_buf)
+        Node::Code(c) => output_buf.push(Found::Code(c)),
+        Node::Heading(h) => output_buf.push(Found::Heading(h.depth, node.to_string())),
+        _ => node.children().into_iter().flatten().for_each(
+            |n| find_nodes(n, output_buf)
         ),
     };
 }
+
+// The heading level that splits a document into sections: the shallowest one
+// used more than once, so a lone `# Title` doesn't count as a section of its
+// own. A document with one heading has one section.
+fn section_level(nodes: &[Found]) -> Option<u8> {
+    let depths: Vec<u8> = nodes
+        .iter()
+        .filter_map(|n| match n {
+            Found::Heading(depth, _) => Some(*depth),
+            Found::Code(_) => None,
+        })
+        .collect();
+    let repeated = depths.iter().filter(|d| depths.iter().filter(|e| e == d).count() > 1).min();
+    repeated.or(depths.iter().min()).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn front_matter_is_not_content() {
+        let md = "---\nglu:\n  command: ls\n---\n```js\n// a.js\nlet a = 1;\n```\n";
+        let doc = parse_document(md).unwrap();
+        assert_eq!(doc.config.command, Some("ls".to_string()));
+        assert_eq!(doc.segments.len(), 1);
+        assert_eq!(doc.segments[0].file_name, "a.js");
+    }
+
+    fn sections(md: &str) -> Vec<Option<(usize, String)>> {
+        parse(md).into_iter().map(|s| s.section.map(|s| (s.number, s.title))).collect()
+    }
+
+    #[test]
+    fn sections_by_heading() {
+        let md = "# Tutorial\n\n```\nintro\n```\n\n\
+            ## Step 1: *Setup*\n\n```\na\n```\n\n### Aside\n\n```\nb\n```\n\n\
+            ## Step 2\n\n```\nc\n```\n";
+        assert_eq!(sections(md), vec![
+            None,
+            Some((1, "Step 1: Setup".to_string())),
+            Some((1, "Step 1: Setup".to_string())),
+            Some((2, "Step 2".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn sections_without_repeats() {
+        assert_eq!(sections("```\na\n```\n"), vec![None]);
+        assert_eq!(sections("# Only\n\n```\na\n```\n"), vec![Some((1, "Only".to_string()))]);
+        assert_eq!(sections("> # Quoted\n>\n> ```\n> a\n> ```\n"), vec![Some((1, "Quoted".to_string()))]);
+    }
+
+    #[test]
+    fn until_section() {
+        let md = "# Tutorial\n\n```\nintro\n```\n\n\
+            ## Step 1: Setup\n\n```\na\n```\n\n\
+            ## Step 2: Think\n\nNo code.\n\n\
+            ## Step 3: Build\n\n```\nb\n```\n";
+        let mut doc = parse_document(md).unwrap();
+        assert_eq!(doc.sections.len(), 3);
+        assert_eq!(doc.find_section("step 2").map(|s| s.number), Some(2));
+        assert_eq!(doc.find_section(" Step 3: build ").map(|s| s.number), Some(3));
+        assert_eq!(doc.find_section("Step 4"), None);
+
+        doc.truncate_after(2);
+        let contents: Vec<&str> = doc.segments.iter().map(|s| s.contents.as_str()).collect();
+        assert_eq!(contents, vec!["intro\n", "a\n"]);
+        assert_eq!(doc.sections.len(), 2);
+
+        doc.truncate_after(0);
+        assert_eq!(doc.segments.len(), 1);
+    }
+}
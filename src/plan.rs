@@ -0,0 +1,183 @@
+use std::collections::BTreeSet;
+use clap::ValueEnum;
+use serde::Serialize;
+use crate::segment::{Detection, Segment};
+use crate::sniff::Guess;
+
+// ----------------------------------------------------------------------------
+// What glu would write, without writing anything
+// ----------------------------------------------------------------------------
+
+#[derive(PartialEq, Debug, Copy, Clone, Default, ValueEnum)]
+pub enum Format {
+    #[default]
+    Table,
+    Json,
+}
+
+/// One block, and the file it would end up in.
+#[derive(PartialEq, Debug, Serialize)]
+pub struct Entry {
+    pub path: String,
+    #[serde(rename = "type")]
+    pub file_type: String,
+    pub document: Option<String>,
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+    pub bytes: usize,
+    pub detection: Detection,
+    /// What the block's contents look like. Blocks with no language have
+    /// one whenever anything matched, and take its type if it's at least
+    /// medium. Labeled blocks only have one when a shebang or modeline
+    /// overruled the label, and that's the type they got.
+    pub guess: Option<Guess>,
+    /// The other blocks that write to the same path
+    pub collides_with: Vec<String>,
+}
+
+fn location(segment: &Segment, i: usize) -> String {
+    match &segment.source {
+        Some(source) => source.to_string(),
+        None => format!("block {}", i + 1),
+    }
+}
+
+/// An entry per block, in document order. Blocks should already have their
+/// front matter applied, but not be merged.
+pub fn plan(blocks: &[Segment]) -> Vec<Entry> {
+    blocks
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| Entry {
+            path: segment.file_name.clone(),
+            file_type: segment.file_type.clone(),
+            document: segment.source.as_ref().and_then(|s| s.document.clone()),
+            start_line: segment.source.as_ref().map(|s| s.start_line),
+            end_line: segment.source.as_ref().map(|s| s.end_line),
+            bytes: segment.contents.len(),
+            detection: segment.detection,
+            guess: segment.guess.clone(),
+            collides_with: blocks
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.file_name == segment.file_name)
+                .map(|(j, other)| location(other, j))
+                .collect(),
+        })
+        .collect()
+}
+
+fn lines(entry: &Entry) -> String {
+    match (entry.start_line, entry.end_line) {
+        (Some(start), Some(end)) => format!("{}-{}", start, end),
+        _ => "-".to_string(),
+    }
+}
+
+/// Entries as a plain text table, one per line. The document column only
+/// shows up when there's more than one document.
+pub fn table(entries: &[Entry]) -> String {
+    let documents: BTreeSet<_> = entries.iter().map(|e| &e.document).collect();
+    let mut rows: Vec<Vec<String>> = vec![];
+    for entry in entries {
+        let mut row = vec![];
+        if documents.len() > 1 {
+            row.push(entry.document.clone().unwrap_or_default());
+        }
+        row.extend([
+            entry.path.clone(),
+            entry.file_type.clone(),
+            lines(entry),
+            entry.bytes.to_string(),
+            entry.detection.to_string(),
+            entry.guess.as_ref().map_or(String::new(), |g| g.to_string()),
+            entry.collides_with.join(", "),
+        ]);
+        rows.push(row);
+    }
+    let mut header = vec!["PATH", "TYPE", "LINES", "BYTES", "DETECTED", "GUESSED", "COLLIDES WITH"];
+    if documents.len() > 1 {
+        header.insert(0, "DOCUMENT");
+    }
+    rows.insert(0, header.iter().map(|h| h.to_string()).collect());
+
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|col| rows.iter().map(|row| row[col].chars().count()).max().unwrap_or_default())
+        .collect();
+    let mut out = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        out += cells.join("  ").trim_end();
+        out += "\n";
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+
+    const DOC: &str = "# Doc\n\n\
+        ```python\n# a.py\nprint(1)\n```\n\n\
+        ```python title=b.py\npass\n```\n\n\
+        ```python\n# a.py\nprint(2)\n```\n\n\
+        ```\nnothing\n```\n";
+
+    #[test]
+    fn entries() {
+        let entries = plan(&parse(DOC));
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0], Entry {
+            path: "a.py".into(),
+            file_type: "python".into(),
+            document: None,
+            start_line: Some(3),
+            end_line: Some(6),
+            bytes: 9,
+            detection: Detection::Comment,
+            guess: None,
+            collides_with: vec!["line 12".into()],
+        });
+        assert_eq!(entries[1].detection, Detection::Meta);
+        assert_eq!(entries[1].collides_with, Vec::<String>::new());
+        assert_eq!(entries[2].collides_with, vec!["line 3".to_string()]);
+        assert_eq!(entries[3].path, "filename.txt");
+        assert_eq!(entries[3].detection, Detection::Fallback);
+    }
+
+    #[test]
+    fn as_table() {
+        let entries = plan(&parse(DOC));
+        assert_eq!(table(&entries[..2]), "\
+            PATH  TYPE    LINES  BYTES  DETECTED  GUESSED  COLLIDES WITH\n\
+            a.py  python  3-6    9      comment            line 12\n\
+            b.py  python  8-10   5      meta\n");
+    }
+
+    #[test]
+    fn guesses() {
+        let entries = plan(&parse("```\npackage main\n```\n\n```\necho hi\n```\n"));
+        assert_eq!(table(&entries), "\
+            PATH          TYPE     LINES  BYTES  DETECTED  GUESSED                 COLLIDES WITH\n\
+            filename.txt  go       1-3    13     fallback  go, medium (heuristic)  line 5\n\
+            filename.txt  unknown  5-7    8      fallback  bash, low (heuristic)   line 1\n");
+        let json = serde_json::to_value(&entries[0]).unwrap();
+        assert_eq!(json["guess"]["confidence"], "medium");
+        assert_eq!(json["guess"]["clue"], "heuristic");
+    }
+
+    #[test]
+    fn as_json() {
+        let entries = plan(&parse(DOC));
+        let json = serde_json::to_value(&entries[1]).unwrap();
+        assert_eq!(json["path"], "b.py");
+        assert_eq!(json["type"], "python");
+        assert_eq!(json["detection"], "meta");
+        assert_eq!(json["start_line"], 8);
+    }
+}
@@ -0,0 +1,257 @@
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use safe_path::scoped_join;
+use crate::config::Config;
+use crate::download;
+use crate::inputs::expand;
+use crate::naming::Namer;
+use crate::merge::{Conflict, FileReport, MergeError, Outcome, merge};
+use crate::parse::{Document, parse_document};
+use crate::segment::{Kind, Segment};
+use crate::steps::{StepError, shell};
+use crate::sync::{Synced, sync_back};
+use crate::writer::write_files;
+
+// ----------------------------------------------------------------------------
+// Documents on disk -> a tree of files, the way the glu command does it
+// ----------------------------------------------------------------------------
+
+/// A document, along with where it came from and its original text.
+pub struct Loaded {
+    pub name: String,
+    pub text: String,
+    pub doc: Document,
+}
+
+/// Read and parse one document, from a path or an http(s) URL.
+pub fn load(mdfile: &str) -> Result<Loaded, Box<dyn Error>> {
+    let md_text = download::get_by_path(mdfile).map_err(|e| format!("{}: {}", mdfile, e))?;
+    let mut doc = parse_document(&md_text).map_err(|e| format!("{}: {}", mdfile, e))?;
+    doc.set_name(mdfile);
+    Ok(Loaded { name: mdfile.to_string(), text: md_text, doc: doc })
+}
+
+/// Every document the inputs name, in order. Inputs can be files, URLs,
+/// directories or globs.
+pub fn load_all(inputs: &[String]) -> Result<Vec<Loaded>, Box<dyn Error>> {
+    let mut docs = vec![];
+    for input in inputs {
+        for mdfile in expand(input).map_err(|e| format!("{}: {}", input, e))? {
+            docs.push(load(&mdfile)?);
+        }
+    }
+    match docs.is_empty() {
+        true => Err("no Markdown documents found".into()),
+        false => Ok(docs),
+    }
+}
+
+/// Every file block in a set of documents, before merging. Unnamed blocks
+/// get names no other block in any of the documents has. Each document
+/// skips files and fills in language defaults for its own blocks. A block's
+/// own conflict policy beats `conflict`, which beats anything in the
+/// document's front matter.
+pub fn blocks<'a, I>(docs: I, conflict: Option<Conflict>) -> Vec<Segment>
+where
+    I: IntoIterator<Item = &'a Document>,
+{
+    let docs: Vec<&Document> = docs.into_iter().collect();
+    let mut namer = Namer::new(docs.iter().flat_map(|d| d.segments.iter()).filter(|s| s.kind == Kind::File));
+    let mut blocks: Vec<Segment> = vec![];
+    for doc in docs {
+        let config = &doc.config;
+        let named = namer.name(config.template(), doc.of_kind(Kind::File));
+        blocks.extend(config.apply(named).into_iter().map(|mut s| {
+            // The command line goes last, over the front matter's language
+            // defaults but not the block's own `conflict=`
+            s.conflict = match conflict {
+                Some(conflict) => s.meta.get("conflict").and_then(Conflict::from_meta).or(Some(conflict)),
+                None => s.conflict.or(config.conflict),
+            };
+            s
+        }));
+    }
+    blocks
+}
+
+/// The files a set of documents unpack to, merged into one tree.
+pub fn files<'a, I>(docs: I, conflict: Option<Conflict>) -> Result<(Vec<Segment>, Vec<FileReport>), MergeError>
+where
+    I: IntoIterator<Item = &'a Document>,
+{
+    merge(blocks(docs, conflict), conflict.unwrap_or_default())
+}
+
+/// Everything `glu export` writes: the file blocks as `blocks` sees them,
+/// named, filtered and with their defaults filled in, then each document's
+/// commands and expected output, in order. Merging the file blocks gives
+/// back what `files` does.
+pub fn exported<'a, I>(docs: I, conflict: Option<Conflict>) -> Vec<Segment>
+where
+    I: IntoIterator<Item = &'a Document>,
+{
+    let docs: Vec<&Document> = docs.into_iter().collect();
+    let mut segments = blocks(docs.iter().copied(), conflict);
+    for doc in docs {
+        segments.extend(doc.segments.iter().filter(|s| s.kind != Kind::File).cloned());
+    }
+    segments
+}
+
+/// Documents' files, written out.
+pub struct Unpacked {
+    /// Where everything else should happen
+    pub dir: PathBuf,
+    /// Every file that was written
+    pub written: Vec<PathBuf>,
+    /// How each file came together
+    pub reports: Vec<FileReport>,
+    /// Files that came from exactly one block, so they can be synced back
+    pub single_block: Vec<Segment>,
+}
+
+/// Write the files from all the documents into one tree under root, then run
+/// the setup script. The first document's settings are the ones that count
+/// for the tree as a whole.
+pub fn unpack(root: &Path, docs: &[Loaded], conflict: Option<Conflict>) -> Result<Unpacked, Box<dyn Error>> {
+    let unpacked = write_tree(root, docs, conflict)?;
+    setup(&docs[0].doc.config, &unpacked.dir)?;
+    Ok(unpacked)
+}
+
+/// Like `unpack`, but leaves running the setup script to the caller.
+pub fn write_tree(root: &Path, docs: &[Loaded], conflict: Option<Conflict>) -> Result<Unpacked, Box<dyn Error>> {
+    let config = &docs[0].doc.config;
+    let (segments, reports) = files(docs.iter().map(|d| &d.doc), conflict)?;
+
+    let dir = unpack_dir(root, config)?;
+    write_files(&dir, &segments)?;
+
+    let written = segments
+        .iter()
+        .map(|s| scoped_join(&dir, &s.file_name))
+        .collect::<io::Result<_>>()?;
+    let single_block = segments
+        .into_iter()
+        .zip(&reports)
+        .filter(|(_, report)| report.outcome == Outcome::Single)
+        .map(|(segment, _)| segment)
+        .collect();
+    Ok(Unpacked { dir: dir, written: written, reports: reports, single_block: single_block })
+}
+
+/// Copy edits to the unpacked files back into the document they came from.
+/// Returns the files that were synced, and any that couldn't be.
+pub fn sync_into(loaded: &Loaded, unpacked: &Unpacked) -> Result<Synced, Box<dyn Error>> {
+    let mine: Vec<Segment> = unpacked.single_block
+        .iter()
+        .filter(|s| s.source.as_ref().and_then(|src| src.document.as_ref()) == Some(&loaded.name))
+        .cloned()
+        .collect();
+    let synced = sync_back(&loaded.text, &mine, &unpacked.dir)?;
+    if synced.files.is_empty() {
+        return Ok(synced);
+    }
+
+    // If someone edited the document in the meantime, our offsets are stale
+    let mdfile = &loaded.name;
+    if fs::read_to_string(mdfile)? != loaded.text {
+        return Err(format!("{} changed while glu was running, not syncing {}", mdfile, synced.files.join(", ")).into());
+    }
+    fs::write(mdfile, &synced.text)?;
+    Ok(synced)
+}
+
+fn unpack_dir(root: &Path, config: &Config) -> io::Result<PathBuf> {
+    match &config.subdir {
+        Some(subdir) => scoped_join(root, subdir),
+        None => Ok(root.to_path_buf()),
+    }
+}
+
+/// Run the document's setup script in dir, if it has one.
+pub fn setup(config: &Config, dir: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(script) = &config.setup {
+        let status = shell(script, dir, &config.env)?;
+        if !status.success() {
+            return Err(StepError { what: "setup script".into(), status: status }.into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{Encoding, from_json, to_json};
+    use tempdir::TempDir;
+
+    #[test]
+    fn unnamed_across_documents() {
+        let a = parse_document("```python\nprint('a')\n```\n").unwrap();
+        let b = parse_document("```python\nprint('b')\n```\n\n```python\n# snippet-01.py\n```\n").unwrap();
+        let names: Vec<String> = blocks([&a, &b], None).into_iter().map(|s| s.file_name).collect();
+        assert_eq!(names, vec!["snippet-01-2.py", "snippet-02.py", "snippet-01.py"]);
+
+        let (files, _) = files([&a, &b], None).unwrap();
+        assert_eq!(files.len(), 3);
+    }
+
+    #[test]
+    fn command_line_conflict_wins() {
+        let doc = parse_document("---\nglu:\n  languages:\n    python:\n      conflict: append\n---\n\
+            ```python\n# a.py\none()\n```\n\n```python\n# a.py\ntwo()\n```\n\n\
+            ```python conflict=append\n# b.py\none()\n```\n\n```python\n# b.py\ntwo()\n```\n").unwrap();
+        let contents = |conflict| -> Vec<String> {
+            files([&doc], conflict).unwrap().0.into_iter().map(|s| s.contents).collect()
+        };
+        assert_eq!(contents(None), vec!["one()\ntwo()\n", "one()\ntwo()\n"]);
+
+        // Only a block's own policy beats --conflict
+        assert_eq!(contents(Some(Conflict::LastWins)), vec!["two()\n", "two()\n"]);
+        let doc = parse_document("```python\n# a.py\none()\n```\n\n```python conflict=append\n# a.py\ntwo()\n```\n").unwrap();
+        let (files, _) = files([&doc], Some(Conflict::Error)).unwrap();
+        assert_eq!(files[0].contents, "one()\ntwo()\n");
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let a = parse_document("---\nglu:\n  skip: [notes.txt]\n  conflict: append\n---\n\
+            ```python\nprint('a')\n```\n\n```text title=notes.txt\nx\n```\n\n\
+            ```python title=main.py\none()\n```\n\n```bash run\npython main.py\n```\n\n```output\n1\n```\n").unwrap();
+        let b = parse_document("```python\n# main.py\ntwo()\n```\n\n```python\nprint('b')\n```\n").unwrap();
+
+        let json = to_json(&exported([&a, &b], None), Encoding::Ndjson).unwrap();
+        let segments = from_json(&json).unwrap();
+        let kinds: Vec<Kind> = segments.iter().map(|s| s.kind).collect();
+        assert_eq!(kinds, vec![Kind::File, Kind::File, Kind::File, Kind::File, Kind::Step, Kind::Expect]);
+
+        let imported = segments.into_iter().filter(|s| s.kind == Kind::File).collect();
+        assert_eq!(merge(imported, Conflict::default()).unwrap(), files([&a, &b], None).unwrap());
+    }
+
+    #[test]
+    fn setup_is_up_to_the_caller() {
+        let text = "---\nglu:\n  setup: touch set-up\n---\n\n```python\n# a.py\npass\n```\n";
+        let docs = [Loaded { name: "doc.md".into(), text: text.into(), doc: parse_document(text).unwrap() }];
+        let tmp = TempDir::new("glu-test").unwrap();
+
+        let written = write_tree(&tmp.path().join("tree"), &docs, None).unwrap();
+        assert!(written.dir.join("a.py").exists());
+        assert!(!written.dir.join("set-up").exists());
+
+        let unpacked = unpack(&tmp.path().join("unpacked"), &docs, None).unwrap();
+        assert!(unpacked.dir.join("set-up").exists());
+    }
+
+    #[test]
+    fn failed_setup() {
+        let text = "---\nglu:\n  setup: exit 5\n---\n";
+        let docs = [Loaded { name: "doc.md".into(), text: text.into(), doc: parse_document(text).unwrap() }];
+        let tmp = TempDir::new("glu-test").unwrap();
+        let err = unpack(tmp.path(), &docs, None).err().unwrap();
+        assert_eq!(err.downcast_ref::<StepError>().and_then(|e| e.status.code()), Some(5));
+    }
+}
@@ -1,22 +1,189 @@
 use markdown::mdast::Code;
+use markdown::unist::Position;
+use std::fmt;
 use std::fmt::Debug;
+use serde::{Deserialize, Serialize};
+use crate::merge::Conflict;
+use crate::meta::Meta;
 use crate::segment_optimizer::SegmentOptimizer;
+use crate::sniff::Guess;
 
-#[derive(PartialEq, Debug)]
+/// Most blocks are files. Some are commands, meant to be run in order once the
+/// files are in place, and some show what those commands should print.
+#[derive(PartialEq, Debug, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// A file to write
+    #[default]
+    File,
+    /// A command, marked `run` or `glu-exec`
+    Step,
+    /// What the command before it should print, marked `output` or `expect`
+    Expect,
+}
+
+pub(crate) fn detect_kind(lang: Option<&str>, meta: &Meta) -> Kind {
+    if meta.has_flag("run") || meta.has_flag("glu-exec") {
+        Kind::Step
+    } else if lang == Some("output") || meta.has_flag("expect") {
+        Kind::Expect
+    } else {
+        Kind::File
+    }
+}
+
+/// How a block's file name was worked out
+#[derive(PartialEq, Debug, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Detection {
+    /// A comment in the block, like `# hello.py`
+    Comment,
+    /// The fence meta, like `title="hello.py"`
+    Meta,
+    /// Nothing to go on, so it got the default name
+    #[default]
+    Fallback,
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Detection::Comment => write!(f, "comment"),
+            Detection::Meta => write!(f, "meta"),
+            Detection::Fallback => write!(f, "fallback"),
+        }
+    }
+}
+
+/// Where a block sits in its Markdown document, fences included
+#[derive(PartialEq, Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Source {
+    /// Which document, when there's more than one around
+    pub document: Option<String>,
+    /// The opening fence's line, 1-indexed like an editor
+    pub start_line: usize,
+    /// The closing fence's line
+    pub end_line: usize,
+    /// Byte offset of the opening fence into the document text
+    pub start_offset: usize,
+    /// Byte offset just past the closing fence
+    pub end_offset: usize,
+}
+
+impl From<&Position> for Source {
+    fn from(item: &Position) -> Source {
+        Source {
+            document: None,
+            start_line: item.start.line,
+            end_line: item.end.line,
+            start_offset: item.start.offset,
+            end_offset: item.end.offset,
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.document {
+            Some(doc) => write!(f, "{}:{}", doc, self.start_line),
+            None => write!(f, "line {}", self.start_line),
+        }
+    }
+}
+
+/// The heading a block sits under, when a document is split into sections.
+/// Sections are numbered from 1, in document order.
+#[derive(PartialEq, Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Section {
+    /// Which section, counting from 1
+    pub number: usize,
+    /// The heading's text
+    pub title: String,
+}
+
+/// One fenced code block, and the file (or command) it turned into.
+#[derive(PartialEq, Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Segment {
+    /// Whether it's a file, a command or expected output
+    pub kind: Kind,
+    /// The language's name, as in [`FileType::name`](crate::FileType::name)
     pub file_type: String,
+    /// Where it goes, relative to the unpacked directory
     pub file_name: String,
+    /// How the file name was worked out
+    pub detection: Detection,
+    /// What goes in the file, ending in a newline
     pub contents: String,
+
+    /// Per-block override for how this lands on earlier blocks for the same file
+    pub conflict: Option<Conflict>,
+
+    /// Unix permissions, if they should differ from the umask default
+    pub mode: Option<u32>,
+
+    /// Everything after the language in the fence
+    pub meta: Meta,
+
+    /// Where it is in the document, if it came from one
+    pub source: Option<Source>,
+
+    /// None for blocks before the first section heading
+    pub section: Option<Section>,
+
+    /// The language its contents suggest, for blocks with no language, or
+    /// labeled ones a shebang or modeline says are something else
+    pub guess: Option<Guess>,
+}
+
+// An explicit `mode=0755` wins. Otherwise, a shebang means it's meant to be run.
+fn detect_mode(meta: &Meta, lines: &[&str]) -> Option<u32> {
+    match meta.get("mode") {
+        Some(m) => u32::from_str_radix(m.trim_start_matches("0o"), 8).ok(),
+        None => match lines.first() {
+            Some(line) if line.starts_with("#!") => Some(0o755),
+            _ => None,
+        },
+    }
 }
 
 impl From<&Code> for Segment {
     fn from(item: &Code) -> Segment {
         let raw_so: SegmentOptimizer = item.into();
-        let so = raw_so.optimize();
+        let meta: Meta = (&item.meta).into();
+
+        // Verbatim blocks skip all the tidying, so `glu pack` can round-trip
+        // files exactly. They need their path in the meta. Commands and their
+        // expected output are never tidied: indentation is part of the output.
+        let kind = detect_kind(item.lang.as_deref(), &meta);
+        let verbatim = meta.has_flag("verbatim") || kind != Kind::File;
+        let so = match verbatim {
+            true => raw_so,
+            false => raw_so.optimize(),
+        };
+        let detection = match (meta.path(), &so.inferred_path) {
+            (Some(_), _) => Detection::Meta,
+            (None, Some(_)) => Detection::Comment,
+            (None, None) => Detection::Fallback,
+        };
+        let contents = match verbatim {
+            true => item.value.clone() + "\n",
+            false => so.lines.join("\n") + "\n",
+        };
         return Segment {
+            kind: kind,
+            guess: so.guess,
             file_type: so.inferred_type.into(),
             file_name: so.inferred_path.unwrap_or("filename.txt".into()),
-            contents: so.lines.join("\n") + "\n",
+            detection: detection,
+            contents: contents,
+            conflict: meta.get("conflict").and_then(Conflict::from_meta),
+            mode: detect_mode(&meta, &so.lines),
+            source: item.position.as_ref().map(Source::from),
+            meta: meta,
+            section: None,
         }
     }
 }
@@ -36,9 +203,117 @@ mod test {
         let seg: Segment = (&code).into();
 
         assert_eq!(seg, Segment {
+            kind: Kind::File,
             file_type: "javascript".to_string(),
             file_name: "foo.js".to_string(),
+            detection: Detection::Comment,
             contents: "let x = 0;\n".to_string(),
+            conflict: None,
+            mode: None,
+            meta: Meta::default(),
+            source: None,
+            section: None,
+            guess: None,
         });
     }
+
+    #[test]
+    fn test_conflict_meta() {
+        let code = Code {
+            meta: Some("conflict=append".to_string()),
+            position: None,
+            value: "// foo.js\n\nlet y = 1;".to_string(),
+            lang: Some("js".to_string()),
+        };
+        let seg: Segment = (&code).into();
+        assert_eq!(seg.conflict, Some(Conflict::Append));
+    }
+
+    #[test]
+    fn test_detect_mode() {
+        let none = Meta::default();
+        assert_eq!(detect_mode(&none, &[]), None);
+        assert_eq!(detect_mode(&none, &["echo hi"]), None);
+        assert_eq!(detect_mode(&none, &["#!/bin/bash", "echo hi"]), Some(0o755));
+
+        assert_eq!(detect_mode(&Meta::from("mode=0644"), &["#!/bin/bash"]), Some(0o644));
+        assert_eq!(detect_mode(&Meta::from("mode=0o700"), &[]), Some(0o700));
+        assert_eq!(detect_mode(&Meta::from("mode=rwx"), &[]), None);
+    }
+
+    #[test]
+    fn test_shebang_after_path_comment() {
+        let code = Code {
+            meta: None,
+            position: None,
+            value: "#!/bin/bash\n# main.sh\n\nnode fib.js".to_string(),
+            lang: Some("bash".to_string()),
+        };
+        let seg: Segment = (&code).into();
+        assert_eq!(seg.file_name, "main.sh");
+        assert_eq!(seg.mode, Some(0o755));
+    }
+
+    #[test]
+    fn test_detect_kind() {
+        assert_eq!(detect_kind(None, &Meta::default()), Kind::File);
+        assert_eq!(detect_kind(Some("bash"), &Meta::from("main.sh")), Kind::File);
+        assert_eq!(detect_kind(Some("bash"), &Meta::from("run")), Kind::Step);
+        assert_eq!(detect_kind(None, &Meta::from("glu-exec")), Kind::Step);
+        assert_eq!(detect_kind(Some("output"), &Meta::default()), Kind::Expect);
+        assert_eq!(detect_kind(Some("text"), &Meta::from("expect")), Kind::Expect);
+    }
+
+    #[test]
+    fn test_verbatim() {
+        let code = Code {
+            meta: Some("title=indented.py verbatim".to_string()),
+            position: None,
+            value: "\n    # other.py\n    pass\n".to_string(),
+            lang: Some("python".to_string()),
+        };
+        let seg: Segment = (&code).into();
+        assert_eq!(seg.file_name, "indented.py");
+        assert_eq!(seg.contents, "\n    # other.py\n    pass\n\n");
+    }
+
+    #[test]
+    fn test_steps_untouched() {
+        let code = |lang: &str, meta: &str, value: &str| Code {
+            meta: Some(meta.to_string()),
+            position: None,
+            value: value.to_string(),
+            lang: Some(lang.to_string()),
+        };
+        let expect: Segment = (&code("text", "expect", "  a\n  b")).into();
+        assert_eq!(expect.contents, "  a\n  b\n");
+        let step: Segment = (&code("bash", "run", "# setup.sh\n  ls")).into();
+        assert_eq!(step.contents, "# setup.sh\n  ls\n");
+    }
+
+    #[test]
+    fn test_detection() {
+        let detect = |meta: Option<&str>, value: &str| {
+            let code = Code {
+                meta: meta.map(|m| m.to_string()),
+                position: None,
+                value: value.to_string(),
+                lang: Some("python".to_string()),
+            };
+            Segment::from(&code).detection
+        };
+        assert_eq!(detect(None, "# a.py\npass"), Detection::Comment);
+        assert_eq!(detect(Some("title=b.py"), "# a.py\npass"), Detection::Meta);
+        assert_eq!(detect(Some("b.py verbatim"), "pass"), Detection::Meta);
+        assert_eq!(detect(None, "pass"), Detection::Fallback);
+        assert_eq!(detect(Some("verbatim"), "# a.py\npass"), Detection::Fallback);
+    }
+
+    #[test]
+    fn test_source_display() {
+        let mut source = Source { start_line: 12, end_line: 15, ..Default::default() };
+        assert_eq!(source.to_string(), "line 12");
+        source.document = Some("docs/01-setup.md".into());
+        assert_eq!(source.to_string(), "docs/01-setup.md:12");
+    }
 }
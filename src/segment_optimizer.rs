@@ -1,27 +1,78 @@
 use regex::Regex;
 use markdown::mdast::Code;
-use crate::filetype::{FileType,detect_path};
+use crate::filetype::{FileType,detect_path,guess_path};
+use crate::meta::Meta;
+use crate::segment::{Kind, detect_kind};
+use crate::sniff::{Clue, Confidence, Guess, sniff};
 
 #[derive(PartialEq, Debug)]
 pub struct SegmentOptimizer<'a> {
     pub lines: Vec<&'a str>,
     pub inferred_type: FileType,
     pub inferred_path: Option<String>,
+    // No language on the fence, so any comment style might name the path
+    pub unlabeled: bool,
+    pub guess: Option<Guess>,
 }
 
 impl <'a> From<&'a Code> for SegmentOptimizer<'a> {
     fn from(c: &'a Code) -> SegmentOptimizer<'a> {
         let lines: Vec<&str> = c.value.lines().collect();
-        let itype: FileType = (&c.lang).into();
+        let meta: Meta = (&c.meta).into();
+        let labeled: FileType = (&c.lang).into();
+
+        // Expected output is whatever it is, whatever it looks like
+        let expect = detect_kind(c.lang.as_deref(), &meta) == Kind::Expect;
+        let unlabeled = c.lang.is_none() && !expect;
+        let tidied = trim_empty_lines(fix_indents(lines.clone()));
+        let guess = match expect {
+            true => None,
+            false => sniff(&tidied),
+        };
+
+        // Any guess is better than nothing for an unlabeled block. Only a
+        // shebang or modeline beats a label, when it isn't just what runs the
+        // label's language (node runs TypeScript), and when the label can't
+        // find the path itself. A path in the meta beats both.
+        let overrides = |g: &Guess| {
+            matches!(g.clue, Clue::Shebang | Clue::Modeline)
+                && g.file_type != String::from(labeled)
+                && !DIALECTS.contains(&(String::from(labeled).as_str(), g.file_type.as_str()))
+                && meta.path().is_none()
+                && (labeled == FileType::UNKNOWN || detect_path(labeled, &tidied).is_none())
+        };
+        let guess = match unlabeled {
+            true => guess,
+            false => guess.filter(overrides),
+        };
+        let sniffed = guess
+            .as_ref()
+            .filter(|g| !unlabeled || g.confidence >= Confidence::Medium)
+            .map_or(labeled, |g| FileType::from(&g.file_type[..]));
+        let itype = match (unlabeled, meta.path()) {
+            (true, Some(path)) if FileType::from_path(&path) != FileType::UNKNOWN => FileType::from_path(&path),
+            _ => sniffed,
+        };
 
+        // A path in the fence meta is explicit, so it skips comment sniffing
         SegmentOptimizer {
             lines: lines,
             inferred_type: itype,
-            inferred_path: None,
+            inferred_path: meta.path(),
+            unlabeled: unlabeled,
+            guess: guess,
         }
     }
 }
 
+// Labels that a shebang for the second language doesn't overrule, because
+// files in the first are run that way too
+const DIALECTS: &[(&str, &str)] = &[
+    ("typescript", "javascript"),
+    ("tsx", "javascript"),
+    ("jsx", "javascript"),
+];
+
 // ----------------------------------------------------------------------------
 // Internal optimizers
 // ----------------------------------------------------------------------------
@@ -29,7 +80,7 @@ impl <'a> From<&'a Code> for SegmentOptimizer<'a> {
 fn find_min_indent(lines: &Vec<&str>) -> usize {
     lines
         .iter()
-        .filter(|s| **s != "")
+        .filter(|s| !s.is_empty())
         .map(|s| 
             s.chars().take_while(|ch| ch.is_whitespace()).count()
         )
@@ -37,37 +88,37 @@ fn find_min_indent(lines: &Vec<&str>) -> usize {
         .unwrap_or_default()
 }
 
-fn trim_indent<'a>(line: &'a str, amount: usize) -> &'a str {
+fn trim_indent(line: &str, amount: usize) -> &str {
     let pat = format!(r"^\s{{0,{}}}(.*)", amount);
     let re = Regex::new(&pat).unwrap();
     let m = re.captures(line).unwrap().get(1).unwrap();
     m.as_str()
 }
 
-fn fix_indents<'a>(lines: Vec<&'a str>) -> Vec<&'a str> {
+fn fix_indents(lines: Vec<&str>) -> Vec<&str> {
     let min_indent = find_min_indent(&lines);
     lines.iter().map(|s| trim_indent(s, min_indent)).collect()
 }
 
-fn trim_empty_lines<'a>(lines: Vec<&'a str>) -> Vec<&'a str> {
+fn trim_empty_lines(lines: Vec<&str>) -> Vec<&str> {
     if lines.is_empty() {
         return lines;
     }
 
     let mut start: usize = 0;
     let mut end: usize = lines.len()-1;
-    while start <= end && lines[start] == "" {
-        start = start + 1;
+    while start <= end && lines[start].is_empty() {
+        start += 1;
     }
-    while end >= start && lines[end] == "" {
-        end = end - 1;
+    while end >= start && lines[end].is_empty() {
+        end -= 1;
     }
 
     return lines[start..end+1].to_vec();
 }
 
-fn pop_line<'a>(n: usize, lines: &Vec<&'a str>) -> Vec<&'a str> {
-    let mut output = lines.clone();
+fn pop_line<'a>(n: usize, lines: &[&'a str]) -> Vec<&'a str> {
+    let mut output = lines.to_vec();
     output.remove(n);
     return output;
 }
@@ -80,23 +131,43 @@ fn path_detect_and_pop<'a>(ft: FileType, lines: &Vec<&'a str>) -> (Option<String
     }
 }
 
+// For unlabeled blocks, where a known extension decides the type too
+fn path_guess_and_pop<'a>(ft: FileType, lines: &Vec<&'a str>) -> (Option<String>, Vec<&'a str>, FileType) {
+    match guess_path(lines) {
+        None => (None, lines.clone(), ft),
+        Some(pd) => {
+            let from_path = FileType::from_path(&pd.path);
+            let ft = match from_path == FileType::UNKNOWN {
+                true => ft,
+                false => from_path,
+            };
+            (Some(pd.path), pop_line(pd.line_number, lines), ft)
+        },
+    }
+}
+
 // ----------------------------------------------------------------------------
 // API for multi-strategy optimization
 // ----------------------------------------------------------------------------
 
 fn opt_once<'a>(so: &SegmentOptimizer<'a>) -> SegmentOptimizer<'a> {
     // TODO: Maybe avoid some clones?
-    let ft = so.inferred_type;
     let tidied_lines = trim_empty_lines(fix_indents(so.lines.clone()));
-    let (path, lines) = match &so.inferred_path {
-        Some(p) => (Some(p.clone()), tidied_lines),
-        None => path_detect_and_pop(ft, &tidied_lines),
+    let (path, lines, ft) = match &so.inferred_path {
+        Some(p) => (Some(p.clone()), tidied_lines, so.inferred_type),
+        None if so.unlabeled => path_guess_and_pop(so.inferred_type, &tidied_lines),
+        None => {
+            let (path, lines) = path_detect_and_pop(so.inferred_type, &tidied_lines);
+            (path, lines, so.inferred_type)
+        },
     };
 
     SegmentOptimizer {
         lines: lines,
         inferred_type: ft,
         inferred_path: path,
+        unlabeled: so.unlabeled,
+        guess: so.guess.clone(),
     }
 }
 
@@ -132,8 +203,10 @@ mod test {
         let so: SegmentOptimizer = (&code).into();
         assert_eq!(so, SegmentOptimizer {
             lines: vec![],
-            inferred_type: FileType::Unknown,
+            inferred_type: FileType::UNKNOWN,
             inferred_path: None,
+            unlabeled: true,
+            guess: None,
         });
     }
 
@@ -149,8 +222,31 @@ mod test {
         let so: SegmentOptimizer = (&code).into();
         assert_eq!(so, SegmentOptimizer {
             lines: vec!["// foo.js", "", "let x = 0;"],
-            inferred_type: FileType::JavaScript,
+            inferred_type: FileType::from("javascript"),
             inferred_path: None,
+            unlabeled: false,
+            guess: None,
+        });
+    }
+
+    #[test]
+    fn test_from_code_meta_path() {
+        let code = Code {
+            meta: Some(r#"title="src/lib.rs""#.to_string()),
+            position: None,
+            value: "// foo.rs\n\nfn foo() {}".to_string(),
+            lang: Some("rust".to_string()),
+        };
+        let so: SegmentOptimizer = (&code).into();
+        assert_eq!(so.inferred_path, Some("src/lib.rs".to_string()));
+
+        // The comment is left alone, since the meta path takes priority
+        assert_eq!(so.optimize(), SegmentOptimizer {
+            lines: vec!["// foo.rs", "", "fn foo() {}"],
+            inferred_type: FileType::from("rust"),
+            inferred_path: Some("src/lib.rs".to_string()),
+            unlabeled: false,
+            guess: None,
         });
     }
 
@@ -226,7 +322,7 @@ mod test {
     #[test]
     fn test_path_detect_and_pop_empty() {
         let lines = vec![];
-        let (found, remaining) = path_detect_and_pop(FileType::JavaScript, &lines);
+        let (found, remaining) = path_detect_and_pop(FileType::from("javascript"), &lines);
         assert_eq!(found, None);
         assert_eq!(remaining, lines);
     }
@@ -234,7 +330,7 @@ mod test {
     #[test]
     fn test_path_detect_and_pop_real() {
         let lines = vec!["// First line", "// foo.js", "// Third line"];
-        let (found, remaining) = path_detect_and_pop(FileType::JavaScript, &lines);
+        let (found, remaining) = path_detect_and_pop(FileType::from("javascript"), &lines);
         assert_eq!(found, Some("foo.js".to_string()));
         assert_eq!(remaining, vec!["// First line", "// Third line"]);
     }
@@ -257,8 +353,10 @@ mod test {
                 "",
                 "",
             ],
-            inferred_type: FileType::JavaScript,
+            inferred_type: FileType::from("javascript"),
             inferred_path: None,
+            unlabeled: false,
+            guess: None,
         };
 
         assert_eq!(optimize(so), SegmentOptimizer {
@@ -268,8 +366,80 @@ mod test {
                 "    ",
                 "}",
             ],
-            inferred_type: FileType::JavaScript,
+            inferred_type: FileType::from("javascript"),
             inferred_path: Some("foo.js".to_string()),
+            unlabeled: false,
+            guess: None,
         });
     }
+
+    #[test]
+    fn test_optimize_unlabeled() {
+        let code = Code {
+            meta: None,
+            position: None,
+            value: "  # config.yaml
+  debug: true".to_string(),
+            lang: None,
+        };
+        let so: SegmentOptimizer = (&code).into();
+        assert_eq!(so.optimize(), SegmentOptimizer {
+            lines: vec!["debug: true"],
+            inferred_type: FileType::from("yaml"),
+            inferred_path: Some("config.yaml".to_string()),
+            unlabeled: true,
+            guess: None,
+        });
+
+        // Not for labeled ones, where the comment has to match the language
+        let code = Code { lang: Some("python".to_string()), ..code };
+        let so: SegmentOptimizer = (&code).into();
+        assert_eq!(so.optimize().inferred_path, None);
+
+        let code = Code { meta: Some("title=util.go".to_string()), lang: None, ..code };
+        let so: SegmentOptimizer = (&code).into();
+        assert_eq!(so.inferred_type, FileType::from("go"));
+    }
+    #[test]
+    fn test_sniffed() {
+        let sniffed = |lang: Option<&str>, value: &str| {
+            let code = Code { meta: None, position: None, value: value.to_string(), lang: lang.map(String::from) };
+            let so = SegmentOptimizer::from(&code).optimize();
+            (so.inferred_type, so.inferred_path, so.guess)
+        };
+        let confidence = |(_, _, guess): (FileType, Option<String>, Option<Guess>)| guess.map(|g| g.confidence);
+
+        // Unlabeled, with the path comment in that language's style
+        let (ft, path, guess) = sniffed(None, "#!/usr/bin/env python3\n# tool.py\nprint(1)");
+        assert_eq!((ft, path), (FileType::from("python"), Some("tool.py".to_string())));
+        assert_eq!(guess.map(|g| g.clue), Some(Clue::Shebang));
+
+        // A low guess is kept, but not used
+        assert_eq!(sniffed(None, "echo hi").0, FileType::UNKNOWN);
+        assert_eq!(confidence(sniffed(None, "echo hi")), Some(Confidence::Low));
+
+        // Mislabeled, but only a sure guess overrides the fence
+        assert_eq!(sniffed(Some("bash"), "#!/usr/bin/env ruby\nputs 1").0, FileType::from("ruby"));
+        assert_eq!(sniffed(Some("bash"), "#!/usr/bin/env ruby\n# tool.rb").1, Some("tool.rb".to_string()));
+        assert_eq!(sniffed(Some("text"), "#!/bin/bash\necho hi").0, FileType::from("bash"));
+
+        // Or when the label finds the path itself, whatever runs the file
+        assert_eq!(sniffed(Some("typescript"), "#!/usr/bin/env node\n// cli.ts\nmain()"),
+            (FileType::from("typescript"), Some("cli.ts".to_string()), None));
+        assert_eq!(sniffed(Some("javascript"), "#!/usr/bin/env -S deno run\n// main.js"),
+            (FileType::from("javascript"), Some("main.js".to_string()), None));
+        assert_eq!(sniffed(Some("bash"), "package main"), (FileType::from("bash"), None, None));
+        assert_eq!(sniffed(Some("python"), "#!/usr/bin/env python3").2, None);
+
+        // Signatures inside a block don't change its label, and a shebang
+        // doesn't turn TypeScript into JavaScript
+        let heredoc = "cat > index.html <<EOF\n<!DOCTYPE html>\n<html></html>\nEOF";
+        assert_eq!(sniffed(Some("bash"), heredoc).0, FileType::from("bash"));
+        assert_eq!(sniffed(Some("bash"), "<?php\necho 1;").0, FileType::from("bash"));
+        assert_eq!(sniffed(Some("typescript"), "#!/usr/bin/env node\nconst x: number = 1;").0,
+            FileType::from("typescript"));
+
+        // Expected output is left alone
+        assert_eq!(sniffed(Some("output"), "#!/bin/bash"), (FileType::UNKNOWN, None, None));
+    }
 }
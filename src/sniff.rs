@@ -0,0 +1,273 @@
+use std::fmt;
+use std::sync::OnceLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::filetype::FileType;
+
+// ----------------------------------------------------------------------------
+// Guessing a block's language from what's in it
+// ----------------------------------------------------------------------------
+//
+// For blocks with no language on the fence, or the wrong one. A shebang or an
+// editor modeline says outright what a file is, and so does a first line like
+// `<?php`. Failing those, a few lines that only really show up in one language
+// (`package main`) make a decent guess, some more than others.
+
+/// How sure a guess is
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    /// Could be something else
+    Low,
+    /// Enough to go on for a block with no language
+    Medium,
+    /// No doubt about it, from a shebang, modeline or opening signature
+    High,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Confidence::Low => write!(f, "low"),
+            Confidence::Medium => write!(f, "medium"),
+            Confidence::High => write!(f, "high"),
+        }
+    }
+}
+
+/// What gave the language away
+#[derive(PartialEq, Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Clue {
+    /// `#!/usr/bin/env python3`
+    Shebang,
+    /// `-*- mode: python -*-` or `vim: set ft=python:`
+    Modeline,
+    /// A line that looks like one language, like `package main`
+    Heuristic,
+}
+
+impl fmt::Display for Clue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Clue::Shebang => write!(f, "shebang"),
+            Clue::Modeline => write!(f, "modeline"),
+            Clue::Heuristic => write!(f, "heuristic"),
+        }
+    }
+}
+
+/// A language worked out from a block's contents.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Guess {
+    /// The language's name, as in [`FileType::name`]
+    pub file_type: String,
+    /// How sure the guess is
+    pub confidence: Confidence,
+    /// What it was based on
+    pub clue: Clue,
+}
+
+impl fmt::Display for Guess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {} ({})", self.file_type, self.confidence, self.clue)
+    }
+}
+
+fn known(ft: FileType) -> Option<FileType> {
+    match ft == FileType::UNKNOWN {
+        true => None,
+        false => Some(ft),
+    }
+}
+
+// `#!/bin/bash`, `#!/usr/bin/env -S deno run`, `#!/usr/bin/python3.12`
+fn shebang(line: &str) -> Option<FileType> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+    }
+    known(FileType::from_interpreter(program)).or_else(|| {
+        let unversioned = program.trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '.');
+        known(FileType::from_interpreter(unversioned))
+    })
+}
+
+fn emacs_modeline() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"-\*-\s*(.*?)\s*-\*-").unwrap())
+}
+
+fn vim_modeline() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?:^|\s)(?:vim?|ex):.*\b(?:ft|filetype|syn|syntax)=([\w+#-]+)").unwrap())
+}
+
+// Emacs looks at the first two lines, vim at the first and last five
+fn modeline(lines: &[&str]) -> Option<FileType> {
+    let emacs = lines.iter().take(2).find_map(|line| {
+        let settings = emacs_modeline().captures(line)?.get(1)?.as_str();
+        let mode = match settings.contains(':') {
+            true => settings
+                .split(';')
+                .filter_map(|s| s.split_once(':'))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("mode"))?
+                .1,
+            false => settings,
+        };
+        known(FileType::from(mode.trim().to_lowercase().as_str()))
+    });
+    let tail = lines.len().saturating_sub(5).max(5);
+    let vim = || {
+        lines.iter().take(5).chain(lines.iter().skip(tail)).find_map(|line| {
+            let name = vim_modeline().captures(line)?.get(1)?.as_str();
+            known(FileType::from(name.to_lowercase().as_str()))
+        })
+    };
+    emacs.or_else(vim)
+}
+
+// A line that looks like one language, what that language is, and how sure
+// it makes us. The High ones are how a file starts, so they only count on the
+// first line; the rest are checked against the first 30.
+const HEURISTICS: &[(&str, &str, Confidence)] = &[
+    (r"^<\?php\b", "php", Confidence::High),
+    (r"^<\?xml\s", "xml", Confidence::High),
+    (r"(?i)^<!DOCTYPE html", "html", Confidence::High),
+    (r"^package main$", "go", Confidence::Medium),
+    (r"^func main\(\) \{", "go", Confidence::Medium),
+    (r"^package [\w.]+;$", "java", Confidence::Medium),
+    (r"^\s*(?:pub\s+)?fn main\(\)", "rust", Confidence::Medium),
+    (r"^use (?:std|crate|super)::", "rust", Confidence::Medium),
+    (r"^#include <(?:iostream|string|vector|memory|map)>", "cpp", Confidence::Medium),
+    (r#"^#include [<"]"#, "c", Confidence::Low),
+    (r"^from [\w.]+ import ", "python", Confidence::Medium),
+    (r"^if __name__ == .__main__.:", "python", Confidence::Medium),
+    (r"^\s*def \w+\(.*\):$", "python", Confidence::Low),
+    (r#"\brequire\(['"][\w./@-]+['"]\)"#, "javascript", Confidence::Medium),
+    (r"^\s*console\.log\(", "javascript", Confidence::Low),
+    (r"^(?:RUN|COPY|ENTRYPOINT|WORKDIR) ", "dockerfile", Confidence::Medium),
+    (r"^FROM [\w./:-]+(?: AS \w+)?$", "dockerfile", Confidence::Low),
+    (r"(?i)^\s*(?:SELECT\s.+\sFROM\s|INSERT INTO\s|CREATE TABLE\s|DELETE FROM\s)", "sql", Confidence::Medium),
+    (r"^\[(?:package|dependencies|tool\.[\w.-]+)\]$", "toml", Confidence::Medium),
+    (r"^defmodule [\w.]+ do$", "elixir", Confidence::Medium),
+    (r"^module [\w.]+ (?:\(.*\) )?where$", "haskell", Confidence::Medium),
+    (r"^\.PHONY:", "makefile", Confidence::Medium),
+    (r"^apiVersion: ", "yaml", Confidence::Medium),
+    (r"^---$", "yaml", Confidence::Low),
+    (r"^\s*local \w+ = require", "lua", Confidence::Medium),
+    (r"^\s*(?:set -e|echo |export \w+=)", "bash", Confidence::Low),
+];
+
+fn heuristics() -> &'static [(Regex, &'static str, Confidence)] {
+    static COMPILED: OnceLock<Vec<(Regex, &str, Confidence)>> = OnceLock::new();
+    COMPILED.get_or_init(|| {
+        HEURISTICS
+            .iter()
+            .map(|(re, lang, confidence)| (Regex::new(re).expect("Failed to compile regex"), *lang, *confidence))
+            .collect()
+    })
+}
+
+// The surest heuristic that matches, the first listed if there's a tie
+fn heuristic(lines: &[&str]) -> Option<(FileType, Confidence)> {
+    let mut best: Option<(FileType, Confidence)> = None;
+    for (re, lang, confidence) in heuristics() {
+        if best.is_some_and(|(_, c)| c >= *confidence) {
+            continue;
+        }
+        let checked = match confidence {
+            Confidence::High => 1,
+            _ => 30,
+        };
+        if lines.iter().take(checked).any(|line| re.is_match(line)) {
+            if let Some(ft) = known(FileType::from(*lang)) {
+                best = Some((ft, *confidence));
+            }
+        }
+    }
+    best
+}
+
+/// Guess a block's language from its lines, after indentation and blank lines
+/// at either end are tidied away.
+pub fn sniff(lines: &[&str]) -> Option<Guess> {
+    let guess = |ft: FileType, confidence, clue| Guess {
+        file_type: ft.into(),
+        confidence: confidence,
+        clue: clue,
+    };
+    if let Some(ft) = lines.first().and_then(|line| shebang(line)) {
+        return Some(guess(ft, Confidence::High, Clue::Shebang));
+    }
+    if let Some(ft) = modeline(lines) {
+        return Some(guess(ft, Confidence::High, Clue::Modeline));
+    }
+    heuristic(lines).map(|(ft, confidence)| guess(ft, confidence, Clue::Heuristic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(lines: &[&str], lang: &str, confidence: Confidence, clue: Clue) {
+        assert_eq!(sniff(lines), Some(Guess { file_type: lang.into(), confidence: confidence, clue: clue }), "{:?}", lines);
+    }
+
+    #[test]
+    fn shebangs() {
+        check(&["#!/bin/bash", "echo hi"], "bash", Confidence::High, Clue::Shebang);
+        check(&["#!/usr/bin/env python3"], "python", Confidence::High, Clue::Shebang);
+        check(&["#!/usr/bin/python3.12 -u"], "python", Confidence::High, Clue::Shebang);
+        check(&["#!/usr/bin/env -S deno run"], "typescript", Confidence::High, Clue::Shebang);
+        assert_eq!(shebang("#!/usr/bin/env"), None);
+        assert_eq!(shebang("#!/usr/bin/frobnicate"), None);
+        assert_eq!(shebang("# not a shebang"), None);
+
+        // Only on the first line
+        assert_eq!(sniff(&["", "#!/bin/bash"]), None);
+    }
+
+    #[test]
+    fn modelines() {
+        check(&["# -*- mode: ruby; coding: utf-8 -*-", "x = 1"], "ruby", Confidence::High, Clue::Modeline);
+        check(&["/* -*- c++ -*- */"], "cpp", Confidence::High, Clue::Modeline);
+        check(&["x = 1", "# vim: set ft=python:"], "python", Confidence::High, Clue::Modeline);
+        check(&["// vi: filetype=javascript"], "javascript", Confidence::High, Clue::Modeline);
+
+        // Vim only looks at the ends of a file
+        let mut lines = vec!["x"; 20];
+        lines[10] = "# vim: ft=python";
+        assert_eq!(modeline(&lines), None);
+        lines[17] = "# vim: ft=ruby";
+        assert_eq!(modeline(&lines), Some(FileType::from("ruby")));
+    }
+
+    #[test]
+    fn signatures() {
+        check(&["<?php", "echo 'hi';"], "php", Confidence::High, Clue::Heuristic);
+        check(&["<!doctype html>", "<html>"], "html", Confidence::High, Clue::Heuristic);
+        check(&["package main", "", "func main() {}"], "go", Confidence::Medium, Clue::Heuristic);
+        check(&["fn main() {", "    println!(\"hi\");", "}"], "rust", Confidence::Medium, Clue::Heuristic);
+        check(&["import os", "from pathlib import Path"], "python", Confidence::Medium, Clue::Heuristic);
+        check(&["FROM alpine", "RUN apk add curl"], "dockerfile", Confidence::Medium, Clue::Heuristic);
+        check(&["echo hello"], "bash", Confidence::Low, Clue::Heuristic);
+
+        // The surer guess wins, wherever it is
+        check(&["#include <stdio.h>", "#include <vector>"], "cpp", Confidence::Medium, Clue::Heuristic);
+        assert_eq!(sniff(&["hello world"]), None);
+
+        // Signatures only count as the first line, not in a heredoc
+        assert_eq!(sniff(&["cat > index.html <<EOF", "<!DOCTYPE html>", "EOF"]), None);
+        assert_eq!(sniff(&["x = 1", "<?xml version=\"1.0\"?>"]), None);
+        assert_eq!(sniff(&[]), None);
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(Confidence::High > Confidence::Medium && Confidence::Medium > Confidence::Low);
+        let guess = Guess { file_type: "go".into(), confidence: Confidence::Medium, clue: Clue::Heuristic };
+        assert_eq!(guess.to_string(), "go, medium (heuristic)");
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use crate::filetype::FileType;
+use crate::segment::Segment;
+
+// ----------------------------------------------------------------------------
+// Running things inside the unpacked directory
+// ----------------------------------------------------------------------------
+
+// Shells that run scripts the way `sh -c` would, more or less
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh"];
+
+// Whether a language is a shell's, going by the interpreters its shebangs
+// name: bash, sh and zsh by default, or a user's own
+pub fn is_shell(ft: FileType) -> bool {
+    ft.language().is_some_and(|l| l.interpreters.iter().any(|i| SHELLS.contains(&i.as_str())))
+}
+
+// The shell to run a script file with: the one its extension names, or bash,
+// since `.sh` files are mostly written for bash
+pub fn shell_for(file_name: &str) -> &'static str {
+    let ext = Path::new(file_name).extension().and_then(|e| e.to_str()).unwrap_or_default();
+    SHELLS.iter().find(|s| **s == ext && ext != "sh").copied().unwrap_or("bash")
+}
+
+pub fn shell(script: &str, dir: &Path, env: &BTreeMap<String, String>) -> io::Result<ExitStatus> {
+    Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .current_dir(dir)
+        .envs(env)
+        .spawn()?
+        .wait()
+}
+
+// Mirror a child's exit the way a shell would: its own exit code, or 128 + N
+// if it was killed by signal N.
+pub fn exit_code(status: ExitStatus) -> u8 {
+    if let Some(code) = status.code() {
+        return u8::try_from(code).unwrap_or(1);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128u8.saturating_add(signal as u8);
+        }
+    }
+    1
+}
+
+// A step or setup script that exited non-zero. glu exits the same way.
+#[derive(Debug)]
+pub struct StepError {
+    // What failed, like `step 2` or `setup script`
+    pub what: String,
+    pub status: ExitStatus,
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed ({})", self.what, self.status)
+    }
+}
+
+impl Error for StepError {}
+
+// Steps are run with `sh -c`, so one labeled with another language would
+// run as something it isn't. Unlabeled ones are taken to be shell.
+pub fn check_step(step: &Segment, number: usize) -> Result<(), String> {
+    let ft = FileType::from(&step.file_type[..]);
+    if ft == FileType::UNKNOWN || is_shell(ft) {
+        return Ok(());
+    }
+    let line = step.source.as_ref().map(|s| format!(" (line {})", s.start_line)).unwrap_or_default();
+    Err(format!(
+        "step {}{} is {}, but steps are run with sh: label it bash, or make it a file and run that",
+        number, line, ft.name(),
+    ))
+}
+
+// Short description of a step for progress output: its first line
+fn summary(step: &Segment) -> &str {
+    step.contents.lines().next().unwrap_or_default()
+}
+
+// Run each step in document order, with output going straight to the
+// terminal. `announce` gets a line like `step 2: cargo test` before each one
+// starts. The first failure stops everything, and nothing runs if a step
+// isn't shell.
+pub fn run_steps<F>(steps: &[Segment], dir: &Path, env: &BTreeMap<String, String>, mut announce: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&str),
+{
+    for (i, step) in steps.iter().enumerate() {
+        check_step(step, i + 1)?;
+    }
+    for (i, step) in steps.iter().enumerate() {
+        announce(&format!("step {}: {}", i + 1, summary(step)));
+        let status = shell(&step.contents, dir, env)?;
+        if !status.success() {
+            return Err(StepError { what: format!("step {}", i + 1), status: status }.into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::Source;
+    use tempdir::TempDir;
+
+    fn step(contents: &str) -> Segment {
+        Segment {
+            contents: contents.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let code = |script: &str| exit_code(shell(script, tmp.path(), &BTreeMap::new()).unwrap());
+        assert_eq!(code("true"), 0);
+        assert_eq!(code("exit 3"), 3);
+        assert_eq!(code("exit 255"), 255);
+        #[cfg(unix)]
+        assert_eq!(code("kill -9 $$"), 137);
+    }
+
+    #[test]
+    fn runs_in_order() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let mut announced: Vec<String> = vec![];
+        run_steps(&[
+            step("echo one > log\n"),
+            step("echo two >> log\n"),
+        ], tmp.path(), &BTreeMap::new(), |line| announced.push(line.to_string())).unwrap();
+
+        let log = std::fs::read_to_string(tmp.path().join("log")).unwrap();
+        assert_eq!(log, "one\ntwo\n");
+        assert_eq!(announced, vec!["step 1: echo one > log", "step 2: echo two >> log"]);
+    }
+
+    #[test]
+    fn stops_on_failure() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let err = run_steps(&[
+            step("touch before\n"),
+            step("exit 3\n"),
+            step("touch after\n"),
+        ], tmp.path(), &BTreeMap::new(), |_| {}).unwrap_err();
+
+        assert_eq!(err.to_string(), "step 2 failed (exit status: 3)");
+        assert_eq!(err.downcast_ref::<StepError>().map(|e| exit_code(e.status)), Some(3));
+        assert!(tmp.path().join("before").exists());
+        assert!(!tmp.path().join("after").exists());
+    }
+
+    #[test]
+    fn shells() {
+        assert!(is_shell(FileType::from("bash")));
+        assert!(is_shell(FileType::from("zsh")));
+        assert!(!is_shell(FileType::from("python")));
+        assert!(!is_shell(FileType::UNKNOWN));
+        assert_eq!(shell_for("build.sh"), "bash");
+        assert_eq!(shell_for("init.zsh"), "zsh");
+        assert_eq!(shell_for("script"), "bash");
+    }
+
+    #[test]
+    fn only_shell() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let python = Segment {
+            file_type: "python".into(),
+            source: Some(Source { start_line: 7, ..Default::default() }),
+            ..step("print('hi')\n")
+        };
+        let err = run_steps(&[step("touch before\n"), python], tmp.path(), &BTreeMap::new(), |_| {}).unwrap_err();
+        assert_eq!(err.to_string(), "step 2 (line 7) is python, but steps are run with sh: \
+            label it bash, or make it a file and run that");
+        assert!(!tmp.path().join("before").exists());
+
+        let bash = Segment { file_type: "bash".into(), ..step("true\n") };
+        run_steps(&[bash, step("true\n")], tmp.path(), &BTreeMap::new(), |_| {}).unwrap();
+    }
+
+    #[test]
+    fn environment() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let env = BTreeMap::from([("GLU_TEST".to_string(), "yes".to_string())]);
+        run_steps(&[step("test \"$GLU_TEST\" = yes\n")], tmp.path(), &env, |_| {}).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use safe_path::scoped_join;
+use crate::segment::{Segment, Source};
+
+// ----------------------------------------------------------------------------
+// Unpacked directory -> the Markdown it came from
+// ----------------------------------------------------------------------------
+//
+// Only the lines between a block's fences are ever rewritten. The fences, the
+// meta, the path comment and everything outside the block stay as they were.
+
+const FENCES: [&str; 2] = ["```", "~~~"];
+
+#[derive(PartialEq, Debug)]
+pub struct Edit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+// What body lines carry in the source, in place of the container markup on
+// the opening fence line: blockquote markers stay, list bullets become spaces.
+fn continuation(prefix: &str) -> String {
+    prefix.chars().map(|ch| if ch == '>' || ch.is_whitespace() { ch } else { ' ' }).collect()
+}
+
+fn strip_continuation<'a>(line: &'a str, cont: &str) -> &'a str {
+    line.strip_prefix(cont)
+        .or_else(|| line.strip_prefix(cont.trim_end()))
+        .unwrap_or(line)
+}
+
+fn is_fence(line: &str) -> bool {
+    FENCES.iter().any(|f| line.trim_start().starts_with(f))
+}
+
+fn line_start(text: &str, offset: usize) -> usize {
+    text[..offset].rfind('\n').map_or(0, |i| i + 1)
+}
+
+#[derive(PartialEq, Debug)]
+struct Span {
+    first: usize,
+    last: usize,
+    skipped: Option<usize>,
+    indent: String,
+}
+
+// Whether a source line becomes `want` once its indentation is trimmed.
+// Every line in a block loses the same indentation, so the first non-empty
+// line decides what it is.
+fn dedents_to(line: &str, want: &str, indent: &mut Option<String>) -> bool {
+    if want.is_empty() {
+        return line.trim().is_empty();
+    }
+    let Some(lead) = line.strip_suffix(want) else { return false };
+    if !lead.chars().all(char::is_whitespace) {
+        return false;
+    }
+    match indent {
+        Some(i) => i == lead,
+        None => {
+            *indent = Some(lead.to_string());
+            true
+        },
+    }
+}
+
+// Find the lines an unpacked file came from: a run of body lines matching it
+// line for line, except that one line (the path comment) may be skipped over.
+fn locate(body: &[&str], old: &[&str]) -> Option<Span> {
+    if old.is_empty() {
+        return None;
+    }
+    (0..body.len()).find_map(|first| {
+        let (mut j, mut k) = (first, 0);
+        let mut skipped = None;
+        let mut indent = None;
+        while k < old.len() {
+            let line = body.get(j)?;
+            if dedents_to(line, old[k], &mut indent) {
+                k += 1;
+            } else if skipped.is_none() && k > 0 {
+                skipped = Some(j);
+            } else {
+                return None;
+            }
+            j += 1;
+        }
+        Some(Span {
+            first: first,
+            last: j - 1,
+            skipped: skipped,
+            indent: indent.unwrap_or_default(),
+        })
+    })
+}
+
+// Work out how to rewrite a block so it unpacks to `new` instead of `old`.
+// None if the block can't be found, or isn't shaped like we expect.
+pub fn block_edit(md_text: &str, source: &Source, old: &str, new: &str) -> Option<Edit> {
+    let open_start = line_start(md_text, source.start_offset);
+    let open_end = open_start + md_text[open_start..].find('\n')?;
+    let open_line = &md_text[open_start..open_end];
+    let fence_at = FENCES
+        .iter()
+        .filter_map(|f| open_line[source.start_offset - open_start..].find(f))
+        .min()? + (source.start_offset - open_start);
+    let cont = continuation(&open_line[..fence_at]);
+
+    // Unclosed blocks run to the end of the document, and aren't worth the risk
+    let close_start = line_start(md_text, source.end_offset);
+    if close_start <= open_end || !is_fence(strip_continuation(&md_text[close_start..source.end_offset], &cont)) {
+        return None;
+    }
+
+    let body_start = open_end + 1;
+    let body: Vec<&str> = match close_start > body_start {
+        true => md_text[body_start..close_start - 1].split('\n').map(|l| strip_continuation(l, &cont)).collect(),
+        false => vec![],
+    };
+    let old_lines: Vec<&str> = old.lines().collect();
+    let span = locate(&body, &old_lines)?;
+
+    let mut new_lines: Vec<String> = new
+        .lines()
+        .map(|l| match l.is_empty() {
+            true => String::new(),
+            false => format!("{}{}", span.indent, l),
+        })
+        .collect();
+    if let Some(skipped) = span.skipped {
+        let at = (skipped - span.first).min(new_lines.len());
+        new_lines.insert(at, body[skipped].to_string());
+    }
+
+    let lines: Vec<String> = body[..span.first]
+        .iter()
+        .map(|l| l.to_string())
+        .chain(new_lines)
+        .chain(body[span.last + 1..].iter().map(|l| l.to_string()))
+        .collect();
+    let text: String = lines
+        .iter()
+        .map(|l| match l.is_empty() {
+            true => format!("{}\n", cont.trim_end()),
+            false => format!("{}{}\n", cont, l),
+        })
+        .collect();
+
+    Some(Edit { start: body_start, end: close_start, text: text })
+}
+
+// What sync_back did
+#[derive(PartialEq, Debug)]
+pub struct Synced {
+    // The document with the changed blocks rewritten
+    pub text: String,
+    // The files whose blocks were rewritten
+    pub files: Vec<String>,
+    // Files that changed but had to be left alone, and why
+    pub warnings: Vec<String>,
+}
+
+// Compare each unpacked file with the block it came from, and rewrite the
+// blocks that changed. Segments should each come from exactly one block.
+pub fn sync_back<R: AsRef<Path>>(md_text: &str, segments: &[Segment], dir: R) -> io::Result<Synced> {
+    let mut edits: Vec<Edit> = vec![];
+    let mut synced: Vec<String> = vec![];
+    let mut warnings: Vec<String> = vec![];
+    for segment in segments {
+        let path = scoped_join(&dir, &segment.file_name)?;
+        let contents = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                warnings.push(format!("{} was deleted, leaving its block alone", segment.file_name));
+                continue;
+            },
+            Err(e) => return Err(e),
+        };
+        if contents == segment.contents {
+            continue;
+        }
+        let edit = segment
+            .source
+            .as_ref()
+            .and_then(|source| block_edit(md_text, source, &segment.contents, &contents));
+        match edit {
+            Some(edit) => {
+                edits.push(edit);
+                synced.push(segment.file_name.clone());
+            },
+            None => warnings.push(format!("couldn't find where {} came from, not syncing it", segment.file_name)),
+        }
+    }
+
+    // Back to front, so earlier offsets stay put
+    edits.sort_by_key(|e| std::cmp::Reverse(e.start));
+    let mut text = md_text.to_string();
+    for edit in edits {
+        text.replace_range(edit.start..edit.end, &edit.text);
+    }
+    Ok(Synced { text: text, files: synced, warnings: warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+    use crate::parse::parse;
+    use crate::writer::write_files;
+
+    // Unpack, let `change` edit the files, sync back
+    fn roundtrip(md: &str, change: impl Fn(&Path)) -> String {
+        let segments = parse(md);
+        let tmp = TempDir::new("glu-test").unwrap();
+        write_files(tmp.path(), &segments).unwrap();
+        change(tmp.path());
+        sync_back(md, &segments, tmp.path()).unwrap().text
+    }
+
+    #[test]
+    fn test_continuation() {
+        assert_eq!(continuation(""), "");
+        assert_eq!(continuation("> "), "> ");
+        assert_eq!(continuation("- "), "  ");
+        assert_eq!(continuation("> 1. "), ">    ");
+    }
+
+    #[test]
+    fn test_locate() {
+        let body = vec!["// foo.js", "", "    let x = 1;", "", "    x += 1;", ""];
+        assert_eq!(locate(&body, &["let x = 1;", "", "x += 1;"]), Some(Span {
+            first: 2,
+            last: 4,
+            skipped: None,
+            indent: "    ".into(),
+        }));
+
+        let body = vec!["#!/bin/bash", "# main.sh", "", "node fib.js"];
+        assert_eq!(locate(&body, &["#!/bin/bash", "", "node fib.js"]), Some(Span {
+            first: 0,
+            last: 3,
+            skipped: Some(1),
+            indent: "".into(),
+        }));
+
+        assert_eq!(locate(&body, &["something else"]), None);
+    }
+
+    #[test]
+    fn unchanged() {
+        let md = "# Title\n\n```js\n// a.js\nlet a = 1;\n```\n";
+        assert_eq!(roundtrip(md, |_| {}), md);
+    }
+
+    #[test]
+    fn changed() {
+        let md = "# Title\n\n```js\n// a.js\n\n  let a = 1;\n```\n\nText after.\n\n```js\n// b.js\nlet b = 2;\n```\n";
+        let synced = roundtrip(md, |dir| {
+            fs::write(dir.join("a.js"), "let a = 10;\nlet aa = 11;\n").unwrap();
+            fs::write(dir.join("b.js"), "let b = 20;\n").unwrap();
+        });
+        assert_eq!(synced, "# Title\n\n```js\n// a.js\n\n  let a = 10;\n  let aa = 11;\n```\n\nText after.\n\n```js\n// b.js\nlet b = 20;\n```\n");
+    }
+
+    #[test]
+    fn shebang() {
+        let md = "```bash\n#!/bin/bash\n# main.sh\n\nnode fib.js\n```\n";
+        let synced = roundtrip(md, |dir| {
+            fs::write(dir.join("main.sh"), "#!/bin/bash\n\nnode fib.js 20\n").unwrap();
+        });
+        assert_eq!(synced, "```bash\n#!/bin/bash\n# main.sh\n\nnode fib.js 20\n```\n");
+    }
+
+    #[test]
+    fn containers() {
+        let md = "> ```py\n> # q.py\n>\n> print(1)\n> ```\n\n- Item\n\n  ```py title=l.py\n  print(2)\n  ```\n";
+        let synced = roundtrip(md, |dir| {
+            fs::write(dir.join("q.py"), "print(10)\n").unwrap();
+            fs::write(dir.join("l.py"), "print(20)\n\nprint(21)\n").unwrap();
+        });
+        assert_eq!(synced, "> ```py\n> # q.py\n>\n> print(10)\n> ```\n\n- Item\n\n  ```py title=l.py\n  print(20)\n\n  print(21)\n  ```\n");
+    }
+
+    #[test]
+    fn multibyte_offsets() {
+        let md = "Ünïcödé ✨ prose\n\n```py\n# u.py\nprint('é')\n```\n";
+        let synced = roundtrip(md, |dir| {
+            fs::write(dir.join("u.py"), "print('ü')\n").unwrap();
+        });
+        assert_eq!(synced, "Ünïcödé ✨ prose\n\n```py\n# u.py\nprint('ü')\n```\n");
+    }
+
+    #[test]
+    fn deleted_files_are_left_alone() {
+        let md = "```py\n# gone.py\nprint(1)\n```\n";
+        let synced = roundtrip(md, |dir| fs::remove_file(dir.join("gone.py")).unwrap());
+        assert_eq!(synced, md);
+
+        let segments = parse(md);
+        let tmp = TempDir::new("glu-test").unwrap();
+        let synced = sync_back(md, &segments, tmp.path()).unwrap();
+        assert_eq!(synced.files, Vec::<String>::new());
+        assert_eq!(synced.warnings, vec!["gone.py was deleted, leaving its block alone"]);
+    }
+}
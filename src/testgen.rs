@@ -0,0 +1,342 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::slice;
+use std::sync::OnceLock;
+use regex::Regex;
+use tempdir::TempDir;
+use crate::doctest::{Outcome, cases, run_cases};
+use crate::filetype::FileType;
+use crate::parse::{Document, parse_document};
+use crate::project::{Loaded, unpack};
+use crate::segment::{Kind, Segment};
+use crate::steps::{is_shell, shell, shell_for};
+use crate::{Error, Options};
+
+// ----------------------------------------------------------------------------
+// Turning the code in docs into `cargo test` tests
+// ----------------------------------------------------------------------------
+//
+// From build.rs:
+//
+//     glu::testgen::write_tests(&["README.md"], out_dir.join("doc_tests.rs"))
+//
+// and then in tests/docs.rs:
+//
+//     include!(concat!(env!("OUT_DIR"), "/doc_tests.rs"));
+//
+// Everything is checked at test time in a freshly unpacked copy of the
+// document, which is why the generated tests need glu as a dev-dependency too.
+// A document with a Cargo.toml gets `cargo test` run on it. Otherwise each
+// Rust file that isn't another's `mod` is built with rustc, and run if it has
+// a `main`. Shell scripts and `run` blocks are run too. Blocks marked `ignore`
+// are left out, and `no_run` ones are only compiled (or syntax checked, for
+// scripts).
+
+// A Rust identifier for some bit of text: `docs/01-intro.md` -> `docs_01_intro_md`
+fn ident(text: &str) -> String {
+    let mut ident: String = text
+        .chars()
+        .map(|ch| match ch.is_ascii_alphanumeric() {
+            true => ch.to_ascii_lowercase(),
+            false => '_',
+        })
+        .collect();
+    ident = ident.trim_matches('_').to_string();
+    if !ident.starts_with(|ch: char| ch.is_ascii_alphabetic()) {
+        ident.insert_str(0, "doc_");
+    }
+    ident
+}
+
+// Make sure no two tests in the generated file share a name
+fn unique(taken: &mut BTreeSet<String>, name: String) -> String {
+    let mut candidate = name.clone();
+    let mut n = 2;
+    while !taken.insert(candidate.clone()) {
+        candidate = format!("{}_{}", name, n);
+        n += 1;
+    }
+    candidate
+}
+
+// `mod util;` in one Rust file makes util.rs part of its crate
+fn mod_decl() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?m)^\s*(?:pub(?:\([\w:]+\))?\s+)?mod\s+(\w+)\s*;").unwrap())
+}
+
+// The Rust files no other file declares as a module, which get compiled on
+// their own. `src/util.rs` and `src/util/mod.rs` are both module `util`.
+fn crate_roots(files: &[Segment]) -> Vec<&Segment> {
+    let rust: Vec<&Segment> = files.iter().filter(|s| FileType::from(&s.file_type[..]).name() == "rust").collect();
+    let declared: BTreeSet<&str> = rust
+        .iter()
+        .flat_map(|s| mod_decl().captures_iter(&s.contents).map(|c| c.get(1).unwrap().as_str()))
+        .collect();
+    rust.into_iter()
+        .filter(|s| {
+            let path = Path::new(&s.file_name);
+            let module = match path.file_stem().and_then(|f| f.to_str()) {
+                Some("mod") => path.parent().and_then(|p| p.file_name()).and_then(|f| f.to_str()),
+                stem => stem,
+            };
+            !module.is_some_and(|m| declared.contains(m))
+        })
+        .collect()
+}
+
+/// Rust source for the tests in one document. `name` is what to call it in
+/// test names and failures, and `path` is where it will be when the tests are
+/// compiled, ideally absolute.
+pub fn generate(md_text: &str, name: &str, path: &Path) -> Result<String, Error> {
+    let doc = parse_document(md_text)?;
+    let files = Options::new().name(name).files(md_text)?;
+    let include = format!("include_str!({:?})", path.to_string_lossy());
+    let prefix = ident(name);
+    let mut taken = BTreeSet::new();
+
+    let mut out = String::new();
+    writeln!(out, "// Generated by glu from {}. Do not edit.\n", name).unwrap();
+    let rust = |s: &&Segment| FileType::from(&s.file_type[..]).name() == "rust";
+    let cargo = files.iter().any(|s| s.file_name == "Cargo.toml");
+    if cargo && files.iter().filter(rust).any(|s| !s.meta.has_flag("ignore")) {
+        let test = unique(&mut taken, format!("{}_cargo", prefix));
+        let run = files.iter().filter(rust).any(|s| !s.meta.has_flag("ignore") && !s.meta.has_flag("no_run"));
+        writeln!(out, "#[test]\nfn {}() {{\n    ::glu::testgen::check_cargo({}, {:?}, {});\n}}\n", test, include, name, run).unwrap();
+    }
+    if !cargo {
+        for segment in crate_roots(&files).into_iter().filter(|s| !s.meta.has_flag("ignore")) {
+            let test = unique(&mut taken, format!("{}_{}", prefix, ident(&segment.file_name)));
+            let run = segment.contents.contains("fn main(") && !segment.meta.has_flag("no_run");
+            writeln!(
+                out,
+                "#[test]\nfn {}() {{\n    ::glu::testgen::check_rust({}, {:?}, {:?}, {});\n}}\n",
+                test, include, name, segment.file_name, run,
+            ).unwrap();
+        }
+    }
+    for segment in files.iter().filter(|s| !s.meta.has_flag("ignore")) {
+        if is_shell(FileType::from(&segment.file_type[..])) {
+            let test = unique(&mut taken, format!("{}_{}", prefix, ident(&segment.file_name)));
+            writeln!(
+                out,
+                "#[test]\nfn {}() {{\n    ::glu::testgen::check_script({}, {:?}, {:?}, {});\n}}\n",
+                test, include, name, segment.file_name, !segment.meta.has_flag("no_run"),
+            ).unwrap();
+        }
+    }
+    if doc.segments.iter().any(|s| s.kind == Kind::Step) {
+        let test = unique(&mut taken, format!("{}_steps", prefix));
+        writeln!(out, "#[test]\nfn {}() {{\n    ::glu::testgen::check_steps({}, {:?});\n}}\n", test, include, name).unwrap();
+    }
+    Ok(out)
+}
+
+/// Generate tests for several documents into one file, for `include!`ing
+/// from an integration test. Meant for build.rs: relative paths are taken
+/// from the crate root, and cargo is told to rerun when a document changes.
+pub fn write_tests<P: AsRef<Path>, Q: AsRef<Path>>(docs: &[P], out: Q) -> Result<(), Error> {
+    let mut generated = String::new();
+    for doc in docs {
+        let doc = doc.as_ref();
+        let md_text = fs::read_to_string(doc)?;
+        println!("cargo:rerun-if-changed={}", doc.display());
+        generated += &generate(&md_text, &doc.to_string_lossy(), &fs::canonicalize(doc)?)?;
+    }
+    fs::write(out, generated)?;
+    Ok(())
+}
+
+// ----------------------------------------------------------------------------
+// Called by the generated tests. They panic, since that's how tests fail.
+// ----------------------------------------------------------------------------
+
+// Unpack the document the way the glu command would, setup script and all.
+// The temp directory goes away when the first half is dropped.
+fn unpacked(md_text: &str, name: &str) -> (TempDir, PathBuf, Document) {
+    let tmp = TempDir::new("glu-test").expect("could not make a temp directory");
+    let doc = parse_document(md_text).unwrap_or_else(|e| panic!("{}: {}", name, e));
+    let loaded = Loaded { name: name.to_string(), text: md_text.to_string(), doc: doc };
+    let dir = match unpack(tmp.path(), slice::from_ref(&loaded), None) {
+        Ok(unpacked) => unpacked.dir,
+        Err(e) => panic!("{}: {}", name, e),
+    };
+    (tmp, dir, loaded.doc)
+}
+
+/// Unpack the document and run one of its shell scripts, failing unless it
+/// exits successfully. Without `run`, it's only syntax checked.
+pub fn check_script(md_text: &str, name: &str, file_name: &str, run: bool) {
+    let (_tmp, dir, doc) = unpacked(md_text, name);
+    let program = shell_for(file_name);
+    let script = match run {
+        true => format!("{} {:?}", program, file_name),
+        false => format!("{} -n {:?}", program, file_name),
+    };
+    let status = shell(&script, &dir, &doc.config.env).unwrap_or_else(|e| panic!("could not run {}: {}", program, e));
+    assert!(status.success(), "{} in {} failed ({})", file_name, name, status);
+}
+
+/// Unpack the document and build one of its Rust files with rustc, along with
+/// any modules it declares, failing unless it compiles. With `run`, the
+/// program is then run in the unpacked directory and has to succeed.
+pub fn check_rust(md_text: &str, name: &str, file_name: &str, run: bool) {
+    let (_tmp, dir, doc) = unpacked(md_text, name);
+    let out = TempDir::new("glu-rustc").expect("could not make a temp directory");
+    let crate_name = ident(Path::new(file_name).file_stem().and_then(|f| f.to_str()).unwrap_or("main"));
+    let binary = out.path().join(&crate_name);
+    let mut rustc = Command::new(std::env::var("RUSTC").unwrap_or("rustc".into()));
+    rustc.args(["--edition", "2021", "--crate-name", &crate_name]).arg(file_name).current_dir(&dir);
+    match run {
+        true => rustc.arg("-o").arg(&binary),
+        false => rustc.args(["--crate-type", "lib", "--emit", "metadata", "--out-dir"]).arg(out.path()),
+    };
+    let status = rustc.status().unwrap_or_else(|e| panic!("could not run rustc: {}", e));
+    assert!(status.success(), "{} in {} doesn't compile", file_name, name);
+    if run {
+        let status = Command::new(&binary)
+            .current_dir(&dir)
+            .envs(&doc.config.env)
+            .status()
+            .unwrap_or_else(|e| panic!("could not run {}: {}", file_name, e));
+        assert!(status.success(), "{} in {} failed ({})", file_name, name, status);
+    }
+}
+
+/// Unpack the document and run `cargo test` in it, failing unless that
+/// passes. Without `run`, the tests are only built.
+pub fn check_cargo(md_text: &str, name: &str, run: bool) {
+    let (_tmp, dir, doc) = unpacked(md_text, name);
+    let mut cargo = Command::new(std::env::var("CARGO").unwrap_or("cargo".into()));
+    cargo.arg("test").current_dir(&dir).envs(&doc.config.env);
+    if !run {
+        cargo.arg("--no-run");
+    }
+    let status = cargo.status().unwrap_or_else(|e| panic!("could not run cargo: {}", e));
+    assert!(status.success(), "cargo test in {} failed ({})", name, status);
+}
+
+/// Unpack the document and check its `run` blocks the way `glu test` does.
+pub fn check_steps(md_text: &str, name: &str) {
+    let (_tmp, dir, doc) = unpacked(md_text, name);
+    let (cases, warnings) = cases(&doc.segments);
+    let reports = run_cases(&cases, &dir, &doc.config.env).expect("could not run steps");
+    let failures: Vec<String> = warnings
+        .into_iter()
+        .chain(reports.iter().filter(|r| r.outcome != Outcome::Pass).map(|r| r.to_string()))
+        .collect();
+    assert!(failures.is_empty(), "{}:\n{}", name, failures.join("\n"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = "# Example\n\n\
+        ```rust\n// src/main.rs\nmod util;\nfn main() { assert_eq!(crate::util::two(), 2); }\n```\n\n\
+        ```rust\n// src/util.rs\npub fn two() -> u8 { 2 }\n```\n\n\
+        ```rust no_run\n// lib.rs\npub fn f() {}\n```\n\n\
+        ```rust ignore\n// broken.rs\nnot rust\n```\n\n\
+        ```bash\n# hello.sh\necho hello > hello.txt\n```\n\n\
+        ```shell\n# hi.bash\necho hi\n```\n\n\
+        ```bash run\nbash hello.sh && cat hello.txt\n```\n\n\
+        ```output\nhello\n```\n";
+
+    const CARGO: &str = "# Crate\n\n\
+        ```toml\n# Cargo.toml\n[package]\nname = \"doc\"\nversion = \"0.1.0\"\nedition = \"2021\"\n```\n\n\
+        ```rust\n// src/lib.rs\nmod util;\n#[test]\nfn works() { assert_eq!(crate::util::two(), 2); }\n```\n\n\
+        ```rust\n// src/util.rs\npub fn two() -> u8 { 2 }\n```\n";
+
+    #[test]
+    fn idents() {
+        assert_eq!(ident("README.md"), "readme_md");
+        assert_eq!(ident("docs/01-intro.md"), "docs_01_intro_md");
+        assert_eq!(ident("/abs/path.md"), "abs_path_md");
+        assert_eq!(ident("01.md"), "doc_01_md");
+
+        let mut taken = BTreeSet::new();
+        assert_eq!(unique(&mut taken, "a".into()), "a");
+        assert_eq!(unique(&mut taken, "a".into()), "a_2");
+        assert_eq!(unique(&mut taken, "a".into()), "a_3");
+    }
+
+    #[test]
+    fn generated() {
+        let out = generate(DOC, "docs/ex.md", Path::new("/abs/docs/ex.md")).unwrap();
+        let call = |test: &str, call: &str| format!("fn {}() {{\n    ::glu::testgen::{};\n}}", test, call);
+        let include = r#"include_str!("/abs/docs/ex.md"), "docs/ex.md""#;
+        assert!(out.contains(&call("docs_ex_md_src_main_rs", &format!(r#"check_rust({}, "src/main.rs", true)"#, include))), "{}", out);
+        assert!(out.contains(&call("docs_ex_md_lib_rs", &format!(r#"check_rust({}, "lib.rs", false)"#, include))), "{}", out);
+        assert!(out.contains(&call("docs_ex_md_hello_sh", &format!(r#"check_script({}, "hello.sh", true)"#, include))), "{}", out);
+        assert!(out.contains(&call("docs_ex_md_hi_bash", &format!(r#"check_script({}, "hi.bash", true)"#, include))), "{}", out);
+        assert!(!out.contains("util_rs"), "{}", out);
+        assert!(!out.contains("broken"), "{}", out);
+        assert!(out.contains("fn docs_ex_md_steps()"), "{}", out);
+
+        // A crate is tested as a whole
+        let out = generate(CARGO, "crate.md", Path::new("/abs/crate.md")).unwrap();
+        assert!(out.contains(&call("crate_md_cargo", r#"check_cargo(include_str!("/abs/crate.md"), "crate.md", true)"#)), "{}", out);
+        assert!(!out.contains("check_rust"), "{}", out);
+    }
+
+    #[test]
+    fn checks() {
+        check_rust(DOC, "ex.md", "src/main.rs", true);
+        check_rust(DOC, "ex.md", "lib.rs", false);
+        check_script(DOC, "ex.md", "hello.sh", true);
+        check_steps(DOC, "ex.md");
+        check_cargo(CARGO, "crate.md", true);
+    }
+
+    #[test]
+    #[should_panic(expected = "src/main.rs in ex.md doesn't compile")]
+    fn failing_rust() {
+        check_rust("```rust\n// src/main.rs\nmod missing;\nfn main() {}\n```\n", "ex.md", "src/main.rs", true);
+    }
+
+    // The generated file, compiled against this build of glu the way a
+    // consumer's integration test would be, and run
+    #[test]
+    fn generated_compiles() {
+        let deps = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+        let rlib = fs::read_dir(&deps)
+            .unwrap()
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.file_name().unwrap().to_string_lossy().starts_with("libglu-") && p.extension().is_some_and(|e| e == "rlib"))
+            .max_by_key(|p| p.metadata().and_then(|m| m.modified()).ok())
+            .expect("cargo test builds libglu for the binary");
+
+        let tmp = TempDir::new("glu-test").unwrap();
+        let doc = tmp.path().join("ex.md");
+        fs::write(&doc, DOC).unwrap();
+        write_tests(&[&doc], tmp.path().join("doc_tests.rs")).unwrap();
+        let binary = tmp.path().join("doc_tests");
+        let status = Command::new("rustc")
+            .args(["--edition", "2021", "--test", "doc_tests.rs", "-o"])
+            .arg(&binary)
+            .arg("-L")
+            .arg(format!("dependency={}", deps.display()))
+            .arg("--extern")
+            .arg(format!("glu={}", rlib.display()))
+            .current_dir(tmp.path())
+            .status()
+            .unwrap();
+        assert!(status.success(), "generated tests don't compile");
+        assert!(Command::new(&binary).status().unwrap().success(), "generated tests fail");
+    }
+
+    #[test]
+    #[should_panic(expected = "fail.sh in ex.md failed")]
+    fn failing_script() {
+        check_script("```bash\n# fail.sh\nexit 3\n```\n", "ex.md", "fail.sh", true);
+    }
+
+    #[test]
+    #[should_panic(expected = "output differs")]
+    fn failing_steps() {
+        check_steps("```bash run\necho yes\n```\n\n```output\nno\n```\n", "ex.md");
+    }
+}
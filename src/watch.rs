@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::inputs::markdown_files;
+
+// ----------------------------------------------------------------------------
+// Noticing when documents change, and cleaning up after them
+// ----------------------------------------------------------------------------
+//
+// Plain polling. Markdown files are small and few, and it saves pulling in a
+// file notification library for every platform.
+
+// What the watched documents looked like at one moment. Any difference at
+// all, including a file appearing or disappearing, means a rebuild.
+pub type Snapshot = BTreeMap<PathBuf, Option<(SystemTime, u64)>>;
+
+// The documents being watched: the file itself, or every Markdown file in a
+// directory (which may come and go).
+pub fn documents(target: &Path) -> io::Result<Vec<PathBuf>> {
+    match target.is_dir() {
+        true => markdown_files(target),
+        false => Ok(vec![target.to_path_buf()]),
+    }
+}
+
+pub fn snapshot(documents: &[PathBuf]) -> Snapshot {
+    documents
+        .iter()
+        .map(|path| {
+            let stamp = fs::metadata(path)
+                .ok()
+                .and_then(|m| Some((m.modified().ok()?, m.len())));
+            (path.clone(), stamp)
+        })
+        .collect()
+}
+
+// Remove files that an earlier build wrote but the current one didn't, along
+// with any directories that leaves empty. Nothing else in the output is ours
+// to touch.
+pub fn remove_stale<R: AsRef<Path>>(root: R, before: &[PathBuf], after: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let root = root.as_ref();
+    let mut removed = vec![];
+    for path in before.iter().filter(|p| !after.contains(p)) {
+        match fs::remove_file(path) {
+            Ok(()) => removed.push(path.clone()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+
+        let mut dir = path.parent();
+        while let Some(d) = dir.filter(|d| d.starts_with(root) && *d != root) {
+            if fs::remove_dir(d).is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_snapshot() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let doc = tmp.path().join("doc.md");
+        let docs = vec![doc.clone()];
+
+        let missing = snapshot(&docs);
+        assert_eq!(missing[&doc], None);
+
+        fs::write(&doc, "# One").unwrap();
+        let one = snapshot(&docs);
+        assert_ne!(one, missing);
+        assert_eq!(snapshot(&docs), one);
+
+        fs::write(&doc, "# Two, longer").unwrap();
+        assert_ne!(snapshot(&docs), one);
+    }
+
+    #[test]
+    fn test_remove_stale() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let root = tmp.path();
+        let keep = root.join("keep.txt");
+        let gone = root.join("src/deep/gone.rs");
+        let unrelated = root.join("src/unrelated.txt");
+        for path in [&keep, &gone, &unrelated] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let (before, after) = (vec![keep.clone(), gone.clone()], vec![keep.clone()]);
+        let removed = remove_stale(root, &before, &after).unwrap();
+        assert_eq!(removed, vec![gone.clone()]);
+        assert!(keep.exists());
+        assert!(unrelated.exists());
+        assert!(!gone.exists());
+        assert!(!root.join("src/deep").exists());
+        assert!(root.join("src").exists());
+    }
+}
@@ -1,17 +1,95 @@
+use std::collections::BTreeMap;
 use std::fs;
+use std::io;
 use std::path::Path;
+use clap::ValueEnum;
 use safe_path::scoped_join;
 use crate::segment::Segment;
 
-pub fn write_files<R: AsRef<Path>>(root: R, segments: &Vec<Segment>) -> std::io::Result<()> {
+// What to do when the output directory already has something in it
+#[derive(PartialEq, Debug, Copy, Clone, Default, ValueEnum)]
+pub enum ExistingDir {
+    /// Stop without touching anything
+    #[default]
+    Refuse,
+    /// Write over the top, leaving unrelated files in place
+    Overwrite,
+    /// Empty the directory first
+    Clean,
+}
+
+fn is_empty_dir(root: &Path) -> io::Result<bool> {
+    Ok(fs::read_dir(root)?.next().is_none())
+}
+
+// Whichever of the inputs (documents, usually) are somewhere under root
+fn inside<'a>(root: &Path, inputs: &[&'a Path]) -> Option<&'a Path> {
+    let root = fs::canonicalize(root).ok()?;
+    inputs.iter().copied().find(|input| fs::canonicalize(input).is_ok_and(|p| p.starts_with(&root)))
+}
+
+// Get a persistent output directory ready for write_files. Cleaning it won't
+// delete any of the inputs.
+pub fn prepare_dir<R: AsRef<Path>>(root: R, policy: ExistingDir, inputs: &[&Path]) -> io::Result<()> {
+    let root = root.as_ref();
+    if !root.exists() {
+        return fs::create_dir_all(root);
+    }
+    if !root.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a directory", root.display())));
+    }
+    match policy {
+        ExistingDir::Refuse if !is_empty_dir(root)? => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} is not empty (use --existing overwrite or clean)", root.display()),
+        )),
+        ExistingDir::Clean => match inside(root, inputs) {
+            Some(input) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} holds {}, so it can't be cleaned", root.display(), input.display()),
+            )),
+            None => remove_dir_all::remove_dir_contents(root),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Same as [`write_files`], but into memory: file name -> contents
+pub fn to_map(segments: &[Segment]) -> BTreeMap<String, String> {
+    segments.iter().map(|s| (s.file_name.clone(), s.contents.clone())).collect()
+}
+
+/// Write each segment to its file under root, creating directories as needed.
+/// File names can't escape root.
+pub fn write_files<R: AsRef<Path>>(root: R, segments: &Vec<Segment>) -> io::Result<()> {
     fs::create_dir_all(&root)?;
     for segment in segments {
         let path = scoped_join(&root, &segment.file_name)?;
-        fs::write(path, &segment.contents)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, &segment.contents)?;
+        set_mode(&path, segment.mode)?;
     }
     Ok(())
 }
 
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: Option<u32>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match mode {
+        Some(m) => fs::set_permissions(path, fs::Permissions::from_mode(m)),
+        None => Ok(()),
+    }
+}
+
+// Nothing sensible to map unix modes onto elsewhere
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: Option<u32>) -> io::Result<()> {
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -37,6 +115,7 @@ mod tests {
                 file_name: "foo.txt".into(),
                 file_type: "text".into(),
                 contents: "Some data".into(),
+                ..Default::default()
             }
         ]).unwrap();
 
@@ -54,6 +133,7 @@ mod tests {
                 file_name: "foo.txt".into(),
                 file_type: "text".into(),
                 contents: "Some data".into(),
+                ..Default::default()
             }
         ]).expect("Establishing dir and files");
         write_files(&root, &vec![]).expect("Running write_files again");
@@ -62,4 +142,134 @@ mod tests {
         let contents: Vec<u8> = std::fs::read(file_path).expect("Making sure file still exists");
         assert_eq!(String::from_utf8(contents), Ok("Some data".to_string()));
     }
+
+    #[test]
+    fn create_nested_file() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        write_files(tmp.path(), &vec![
+            Segment {
+                file_name: "src/deeply/nested.rs".into(),
+                file_type: "rust".into(),
+                contents: "fn main() {}\n".into(),
+                ..Default::default()
+            }
+        ]).unwrap();
+        assert!(tmp.path().join("src/deeply/nested.rs").is_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = TempDir::new("glu-test").unwrap();
+        write_files(tmp.path(), &vec![
+            Segment {
+                file_name: "run.sh".into(),
+                file_type: "bash".into(),
+                contents: "#!/bin/sh\necho hi\n".into(),
+                mode: Some(0o755),
+                ..Default::default()
+            },
+            Segment {
+                file_name: "secret.txt".into(),
+                file_type: "text".into(),
+                contents: "hunter2\n".into(),
+                mode: Some(0o600),
+                ..Default::default()
+            },
+        ]).unwrap();
+
+        let mode = |name: &str| fs::metadata(tmp.path().join(name)).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode("run.sh"), 0o755);
+        assert_eq!(mode("secret.txt"), 0o600);
+    }
+
+    fn populated_root(tmp: &TempDir) -> std::path::PathBuf {
+        let root = scoped_join(tmp.path(), "root").unwrap();
+        write_files(&root, &vec![
+            Segment {
+                file_name: "old.txt".into(),
+                file_type: "text".into(),
+                contents: "Old data".into(),
+                ..Default::default()
+            }
+        ]).unwrap();
+        root
+    }
+
+    #[test]
+    fn prepare_missing_dir() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let root = scoped_join(tmp.path(), "a/b").unwrap();
+        prepare_dir(&root, ExistingDir::Refuse, &[]).unwrap();
+        assert!(root.is_dir());
+    }
+
+    #[test]
+    fn prepare_empty_dir() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        prepare_dir(tmp.path(), ExistingDir::Refuse, &[]).expect("Empty dirs are fine to use");
+    }
+
+    #[test]
+    fn prepare_refuse() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let root = populated_root(&tmp);
+        let err = prepare_dir(&root, ExistingDir::Refuse, &[]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(root.join("old.txt").exists());
+    }
+
+    #[test]
+    fn prepare_overwrite() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let root = populated_root(&tmp);
+        prepare_dir(&root, ExistingDir::Overwrite, &[]).unwrap();
+        assert!(root.join("old.txt").exists());
+    }
+
+    #[test]
+    fn prepare_clean() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let root = populated_root(&tmp);
+        prepare_dir(&root, ExistingDir::Clean, &[]).unwrap();
+        assert!(root.is_dir());
+        assert!(!root.join("old.txt").exists());
+    }
+
+    #[test]
+    fn prepare_clean_keeps_inputs() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let root = populated_root(&tmp);
+        let doc = root.join("sub/doc.md");
+        fs::create_dir_all(doc.parent().unwrap()).unwrap();
+        fs::write(&doc, "# Doc").unwrap();
+
+        let err = prepare_dir(&root, ExistingDir::Clean, &[Path::new("elsewhere.md"), &doc]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(doc.exists() && root.join("old.txt").exists());
+
+        prepare_dir(root.join("sub"), ExistingDir::Overwrite, &[&doc]).unwrap();
+        prepare_dir(&root, ExistingDir::Clean, &[&tmp.path().join("elsewhere.md")]).unwrap();
+        assert!(!doc.exists());
+    }
+
+    #[test]
+    fn prepare_not_a_dir() {
+        let tmp = TempDir::new("glu-test").unwrap();
+        let file = tmp.path().join("file");
+        fs::write(&file, "").unwrap();
+        assert!(prepare_dir(&file, ExistingDir::Clean, &[]).is_err());
+    }
+
+    #[test]
+    fn map() {
+        let segments = [
+            Segment { file_name: "b.txt".into(), contents: "B".into(), ..Default::default() },
+            Segment { file_name: "a/a.txt".into(), contents: "A".into(), ..Default::default() },
+        ];
+        let map = to_map(&segments);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["a/a.txt", "b.txt"]);
+        assert_eq!(map["b.txt"], "B");
+    }
 }